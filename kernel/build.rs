@@ -29,11 +29,17 @@ pub fn main() {
 
     let baseline_y = horiz_metrics.ascent.ceil() as i32;
 
+    let first_char = CHAR_RANGE.start();
+    let last_char = CHAR_RANGE.end();
+
     writeln!(
         &out_file,
         "pub const CHAR_WIDTH: u64 = {char_width};
         pub const CHAR_HEIGHT: u64 = {char_height};
 
+        pub const FIRST_CHAR: char = {first_char:?};
+        pub const LAST_CHAR: char = {last_char:?};
+
         pub type Glyph = &'static [&'static [u8; {char_width}]; {char_height}];
 
         pub const GLYPHS: &[Glyph] = &["
@@ -66,4 +72,28 @@ pub fn main() {
     }
 
     writeln!(&out_file, "];").unwrap();
+
+    // Replacement glyph for non-ASCII chars, a box outline as tall as a capital letter
+    let (cap_metrics, _) = font.rasterize('H', FONT_SIZE);
+    let box_top = (baseline_y - cap_metrics.height as i32 - cap_metrics.ymin).max(0) as usize;
+    let box_bottom = (box_top + cap_metrics.height).min(char_height) - 1;
+    let box_coverage = (u8::MAX as f32 * BRIGHTNESS_SCALE) as u8;
+
+    let mut bitmap = vec![vec![0u8; char_width]; char_height];
+
+    for (y, row) in bitmap.iter_mut().enumerate().take(box_bottom + 1).skip(box_top) {
+        for (x, pixel) in row.iter_mut().enumerate().take(char_width - 1).skip(1) {
+            if y == box_top || y == box_bottom || x == 1 || x == char_width - 2 {
+                *pixel = box_coverage;
+            }
+        }
+    }
+
+    write!(&out_file, "pub const REPLACEMENT_GLYPH: Glyph = &[").unwrap();
+
+    for row in &bitmap {
+        write!(&out_file, "&{row:?}, ").unwrap();
+    }
+
+    writeln!(&out_file, "];").unwrap();
 }
\ No newline at end of file
@@ -2,21 +2,40 @@ use core::alloc::Layout;
 use core::mem::ManuallyDrop;
 use core::ptr::NonNull;
 
+use crate::boot::{self, Phase};
 use crate::heap::{self, SLOT_ALIGN, SLOT_SIZE};
+use crate::kassert;
 
 union Node<T> {
     data: ManuallyDrop<T>,
     next_free: Option<NonNull<Self>>,
 }
 
+/// Most nodes a slot can hold, nodes are at least as large as the `next_free` pointer
+const MAX_NODES_PER_SLOT: usize = SLOT_SIZE / core::mem::size_of::<Option<NonNull<u8>>>();
+
+/// A typed object allocator that carves heap slots into fixed size nodes
+///
+/// `Arena` holds raw pointers and is therefore neither `Send` nor `Sync`. This is load bearing:
+/// the slot metadata it updates is accessed without any locking or atomics (see
+/// [`heap::update_slot_metadata()`]), so an arena and the slots it owns must never be shared
+/// between cores while the slots are allocated
+///
+/// Objects are never dropped by the arena, not in [`Arena::free()`] and not when the arena itself
+/// is dropped. Owners of objects that need dropping (like [`crate::map::Map`]) have to drop them in
+/// place before freeing them
 pub struct Arena<T> {
     freelist: Option<NonNull<Node<T>>>,
     slot_list: NonNull<u8>,
+
+    /// Bitmap of which nodes in the slot are allocated, indexed by node
+    ///
+    /// A `Node` can't tell by itself whether it holds data or is in the freelist, so this is
+    /// needed for [`Arena::iter()`] to skip free nodes
+    live: [u64; MAX_NODES_PER_SLOT / 64],
 }
 
 impl<T> Arena<T> {
-    pub const _DROP_CHECK: () = assert!(!core::mem::needs_drop::<T>());
-
     pub const NODES_PER_SLOT: usize = SLOT_SIZE / core::mem::size_of::<Node<T>>();
 
     const _ALIGN_CHECK: () = {
@@ -31,12 +50,15 @@ impl<T> Arena<T> {
     };
 
     pub fn new() -> Self {
+        boot::require(Phase::Heap, "Arena::new()");
+
         let slot = heap::alloc_slot();
         let freelist_head = Self::init_slot(slot);
 
         Self {
             freelist: Some(freelist_head),
             slot_list: slot,
+            live: [0; MAX_NODES_PER_SLOT / 64],
         }
     }
 
@@ -46,9 +68,11 @@ impl<T> Arena<T> {
             todo!("Allocate additional slot")
         };
 
-        // Decrement alloc count
+        // Increment alloc count
+        // Safety: `free_node_ptr` points into a slot owned by this arena, and the arena is not shared
+        // between cores so we have exclusive access to its metadata
         unsafe {
-            heap::update_slot_metadata(free_node_ptr.cast(), |(alloc_count, _next_slot)| *alloc_count += 1);
+            heap::update_slot_metadata(free_node_ptr.cast(), |metadata| metadata.alloc_count += 1);
         }
 
         // Safety: `free_node` always points to a node that has been previously initialised by `init_slot()`
@@ -58,34 +82,78 @@ impl<T> Arena<T> {
         self.freelist = unsafe { free_node.next_free };
         free_node.data = ManuallyDrop::new(value);
 
+        self.set_live(self.node_idx(free_node_ptr), true);
+
         free_node_ptr.cast::<T>()
     }
 
     pub fn free(&mut self, ptr: NonNull<T>) {
         let mut node_ptr = ptr.cast::<Node<T>>();
+
+        // Mark the node as free, catching double frees while we're at it
+        let idx = self.node_idx(node_ptr);
+        kassert!(self.is_live(idx), "Arena node {ptr:p} freed twice");
+        self.set_live(idx, false);
+
         let node = unsafe { node_ptr.as_mut() };
 
         // Decrement alloc count
+        // Even if the slot has no allocations left it is kept around, since its nodes are still in the
+        // freelist. Slots are only returned to the heap when the arena is dropped
+        // Safety: `node_ptr` points into a slot owned by this arena, and the arena is not shared
+        // between cores so we have exclusive access to its metadata
         unsafe {
-            heap::update_slot_metadata(node_ptr.cast(), |(alloc_count, _next_slot)| {
-                *alloc_count -= 1;
-
-                if *alloc_count == 0 {
-                    todo!("Free this slot");
-                }
-            });
+            heap::update_slot_metadata(node_ptr.cast(), |metadata| metadata.alloc_count -= 1);
         };
 
         *node = Node { next_free: self.freelist };
         self.freelist = Some(node_ptr);
     }
 
+    /// Returns an iterator over all the currently allocated objects
+    #[allow(dead_code, reason = "Nothing needs to enumerate arena objects yet")]
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let nodes = self.slot_list.cast::<Node<T>>();
+
+        (0..Self::NODES_PER_SLOT)
+            .filter(|&idx| self.is_live(idx))
+            .map(move |idx| {
+                // Safety: `idx` is within the slot
+                let node_ptr = unsafe { nodes.add(idx) };
+
+                // Safety: The node is live so it holds an initialized `T`, and it can't be freed
+                // while `self` is borrowed
+                unsafe { node_ptr.cast::<T>().as_ref() }
+            })
+    }
+
+    /// Index of the node `node_ptr` points to within the slot
+    fn node_idx(&self, node_ptr: NonNull<Node<T>>) -> usize {
+        (node_ptr.addr().get() - self.slot_list.addr().get()) / core::mem::size_of::<Node<T>>()
+    }
+
+    fn is_live(&self, idx: usize) -> bool {
+        let word = self.live.get(idx / 64).expect("Node index out of range");
+        word & (1 << (idx % 64)) != 0
+    }
+
+    fn set_live(&mut self, idx: usize, live: bool) {
+        let word = self.live.get_mut(idx / 64).expect("Node index out of range");
+
+        if live {
+            *word |= 1 << (idx % 64);
+        } else {
+            *word &= !(1 << (idx % 64));
+        }
+    }
+
     #[allow(clippy::indexing_slicing, reason = "Too verbose without it, slice len is const as well")]
     fn init_slot(slot: NonNull<u8>) -> NonNull<Node<T>> {
+        // Safety: `slot` was just allocated from the heap and is exclusively owned by us
         unsafe {
-            heap::update_slot_metadata(slot, |(alloc_count, next_slot_addr)| {
-                *alloc_count = 0;
-                *next_slot_addr = 0;
+            heap::update_slot_metadata(slot, |metadata| {
+                metadata.alloc_count = 0;
+                metadata.next_slot = 0;
             });
         }
 
@@ -107,3 +175,38 @@ impl<T> Arena<T> {
         NonNull::new(freelist_head).expect("freelist_head ptr is null")
     }
 }
+
+#[allow(dead_code, reason = "Not used outside of debugging yet")]
+impl<T> Arena<T> {
+    /// Number of currently allocated objects
+    pub fn len(&self) -> usize {
+        // Safety: The slot is owned by this arena, and the arena is not shared between cores
+        let alloc_count = unsafe { heap::slot_metadata(self.slot_list) }.alloc_count;
+
+        #[allow(clippy::cast_possible_truncation, reason = "usize and u64 have same size here")]
+        let alloc_count = alloc_count as usize;
+
+        alloc_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total number of objects the arena can hold
+    #[allow(clippy::unused_self, reason = "Will depend on the number of slots once arenas can grow")]
+    pub fn capacity(&self) -> usize {
+        // Arenas currently only ever have a single slot
+        Self::NODES_PER_SLOT
+    }
+}
+
+impl<T> Drop for Arena<T> {
+    /// Returns the arena's slot to the heap
+    ///
+    /// Objects that are still allocated are not dropped, they have to be freed (and dropped if
+    /// needed) by their owner before the arena goes away
+    fn drop(&mut self) {
+        heap::free_slot(self.slot_list);
+    }
+}
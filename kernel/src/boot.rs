@@ -0,0 +1,73 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Boot phases, in the order they are completed during boot
+///
+/// Each phase is completed by the corresponding subsystem's `init()`
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Phase {
+    /// Nothing has been initialized yet
+    Start,
+    DebugPrint,
+    CpuId,
+    Heap,
+    Gdt,
+    Interrupt,
+    Symbols,
+}
+
+impl Phase {
+    const ALL: [Self; 7] = [
+        Self::Start,
+        Self::DebugPrint,
+        Self::CpuId,
+        Self::Heap,
+        Self::Gdt,
+        Self::Interrupt,
+        Self::Symbols,
+    ];
+
+    fn from_index(idx: u8) -> Self {
+        Self::ALL
+            .get(usize::from(idx))
+            .copied()
+            .expect("Invalid boot phase")
+    }
+}
+
+/// Index of the last completed boot phase
+static PHASE: AtomicU8 = AtomicU8::new(Phase::Start as u8);
+
+/// Last completed boot phase
+pub fn current() -> Phase {
+    Phase::from_index(PHASE.load(Ordering::Acquire))
+}
+
+/// Marks `phase` as completed
+///
+/// # Panics
+/// Panics if the phase before `phase` isn't the last completed one, which means that `phase`
+/// is being initialized out of order or twice
+pub fn complete(phase: Phase) {
+    let prev = (phase as u8)
+        .checked_sub(1)
+        .expect("Boot phase `Start` can't be completed");
+
+    if let Err(last) = PHASE.compare_exchange(prev, phase as u8, Ordering::AcqRel, Ordering::Acquire) {
+        panic!(
+            "Boot phase {phase:?} completed out of order, expected {:?} to be the last completed phase but it was {:?}",
+            Phase::from_index(prev),
+            Phase::from_index(last)
+        );
+    }
+}
+
+/// Asserts that `phase` has been completed
+///
+/// `user` names the code that depends on `phase`, for the panic message
+pub fn require(phase: Phase, user: &str) {
+    let current = current();
+    assert!(
+        current >= phase,
+        "{user} requires boot phase {phase:?}, but the last completed phase is {current:?}"
+    );
+}
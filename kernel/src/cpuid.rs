@@ -1,12 +1,114 @@
+use arrayvec::ArrayVec;
 use raw_cpuid::CpuId;
 
 use crate::{
+    boot::{self, Phase},
     debug_print,
-    debug_print::{HEADING, SUBHEADING},
+    debug_print::{Level, HEADING, SUBHEADING},
     debug_println,
+    sync::Once,
 };
 
-/// Checks for required CPU features
+/// Optional CPU features, detected once by [`check()`]
+///
+/// Required features aren't included since `check()` makes sure they're all supported, apart
+/// from 1 GiB pages which code deciding on page sizes queries from here
+#[allow(clippy::struct_excessive_bools, reason = "Each bool is an independent feature flag")]
+pub struct CpuInfo {
+    pages_1gib: bool,
+    syscall_sysret: bool,
+    execute_disable: bool,
+    fsgsbase: bool,
+    pcid: bool,
+    x2apic: bool,
+    smep: bool,
+    smap: bool,
+    invariant_tsc: bool,
+}
+
+impl CpuInfo {
+    /// 1 GiB pages (CPUID `0x8000_0001` EDX bit 26)
+    ///
+    /// This is a required feature, so it's always `true` once [`check()`] has passed
+    #[allow(dead_code, reason = "There's no page table code to use it yet")]
+    pub fn supports_1gib_pages(&self) -> bool {
+        self.pages_1gib
+    }
+
+    /// `SYSCALL`/`SYSRET` instructions
+    pub fn supports_syscall(&self) -> bool {
+        self.syscall_sysret
+    }
+
+    /// No-execute page protection
+    pub fn supports_execute_disable(&self) -> bool {
+        self.execute_disable
+    }
+
+    /// `RDFSBASE`/`WRFSBASE`/`RDGSBASE`/`WRGSBASE` instructions
+    pub fn supports_fsgsbase(&self) -> bool {
+        self.fsgsbase
+    }
+
+    /// Process context identifiers, for tagging TLB entries per address space
+    pub fn supports_pcid(&self) -> bool {
+        self.pcid
+    }
+
+    pub fn supports_x2apic(&self) -> bool {
+        self.x2apic
+    }
+
+    /// Supervisor mode execution prevention
+    pub fn supports_smep(&self) -> bool {
+        self.smep
+    }
+
+    /// Supervisor mode access prevention
+    pub fn supports_smap(&self) -> bool {
+        self.smap
+    }
+
+    /// TSC that runs at a constant rate in all power states
+    pub fn supports_invariant_tsc(&self) -> bool {
+        self.invariant_tsc
+    }
+
+    /// Optional feature names paired with whether they're supported, for printing
+    fn features(&self) -> [(&'static str, bool); 8] {
+        [
+            ("SYSCALL/SYSRET", self.supports_syscall()),
+            ("Execute disable", self.supports_execute_disable()),
+            ("FSGSBASE", self.supports_fsgsbase()),
+            ("PCID", self.supports_pcid()),
+            ("x2APIC", self.supports_x2apic()),
+            ("SMEP", self.supports_smep()),
+            ("SMAP", self.supports_smap()),
+            ("Invariant TSC", self.supports_invariant_tsc()),
+        ]
+    }
+}
+
+static CPU_INFO: Once<CpuInfo> = Once::new();
+
+/// Returns the optional CPU features detected by [`check()`]
+#[allow(dead_code, reason = "Nothing depends on optional features yet")]
+pub fn info() -> &'static CpuInfo {
+    CPU_INFO.get().expect("cpuid::check() not called yet")
+}
+
+/// Names of the features in `features` that aren't supported
+///
+/// Features are given as `(name, supported)`
+fn missing_features<const N: usize>(features: [(&'static str, bool); N]) -> ArrayVec<&'static str, N> {
+    features
+        .into_iter()
+        .filter(|&(_, supported)| !supported)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Checks for required CPU features, and detects optional ones
 pub fn check() {
     debug_println!(HEADING; "Checking CPU features:");
 
@@ -44,20 +146,52 @@ pub fn check() {
     // Level 3 is the minimum level required by Kasumi and all components are
     // compiled targeting this level. We only check for level 3 features since
     // it implies all the previous levels are also supported
-    assert!(feature_info.has_avx(), "CPU does not support x86_64-v3 feature: AVX");
-    assert!(ext_info.has_avx2(), "CPU does not support x86_64-v3 feature: AVX 2");
-    assert!(ext_info.has_bmi1(), "CPU does not support x86_64-v3 feature: BMI 1");
-    assert!(ext_info.has_bmi2(), "CPU does not support x86_64-v3 feature: BMI 2");
-    assert!(feature_info.has_f16c(), "CPU does not support x86_64-v3 feature: F16C");
-    assert!(feature_info.has_fma(), "CPU does not support x86_64-v3 feature: FMA");
-    assert!(ext_ident.has_lzcnt(), "CPU does not support x86_64-v3 feature: LZCNT");
-    assert!(feature_info.has_movbe(), "CPU does not support x86_64-v3 feature: MOVBE");
-    assert!(feature_info.has_xsave(), "CPU does not support x86_64-v3 feature: XSAVE");
-
-    // Check other required features
-    assert!(feature_info.has_apic(), "CPU does not support APIC");
-    assert!(power_info.has_arat(), "CPU does not support Always-Running-APIC-Timer (ARAT)");
-    assert!(ext_ident.has_1gib_pages(), "CPU does not support 1 GiB huge pages");
+    //
+    // Other required features are checked along with it, and all missing features
+    // are reported together so they can be fixed in one go
+    let missing = missing_features([
+        ("x86_64-v3 feature: AVX", feature_info.has_avx()),
+        ("x86_64-v3 feature: AVX 2", ext_info.has_avx2()),
+        ("x86_64-v3 feature: BMI 1", ext_info.has_bmi1()),
+        ("x86_64-v3 feature: BMI 2", ext_info.has_bmi2()),
+        ("x86_64-v3 feature: F16C", feature_info.has_f16c()),
+        ("x86_64-v3 feature: FMA", feature_info.has_fma()),
+        ("x86_64-v3 feature: LZCNT", ext_ident.has_lzcnt()),
+        ("x86_64-v3 feature: MOVBE", feature_info.has_movbe()),
+        ("x86_64-v3 feature: XSAVE", feature_info.has_xsave()),
+        ("APIC", feature_info.has_apic()),
+        ("Always-Running-APIC-Timer (ARAT)", power_info.has_arat()),
+        ("1 GiB huge pages", ext_ident.has_1gib_pages()),
+    ]);
+
+    for name in &missing {
+        debug_println!(level: Level::Error; "CPU does not support {name}");
+    }
+
+    assert!(missing.is_empty(), "CPU does not support {} required features", missing.len());
 
     debug_println!(SUBHEADING; "All required features supported");
+
+    // Detect optional features
+    let info = CPU_INFO.call_once(|| CpuInfo {
+        pages_1gib: ext_ident.has_1gib_pages(),
+        syscall_sysret: ext_ident.has_syscall_sysret(),
+        execute_disable: ext_ident.has_execute_disable(),
+        fsgsbase: ext_info.has_fsgsbase(),
+        pcid: feature_info.has_pcid(),
+        x2apic: feature_info.has_x2apic(),
+        smep: ext_info.has_smep(),
+        smap: ext_info.has_smap(),
+        invariant_tsc: cpuid
+            .get_advanced_power_mgmt_info()
+            .is_some_and(|info| info.has_invariant_tsc()),
+    });
+
+    debug_println!(SUBHEADING; "Optional features:");
+
+    for (name, supported) in info.features() {
+        debug_println!(SUBHEADING; "    {name}: {}", if supported { "yes" } else { "no" });
+    }
+
+    boot::complete(Phase::CpuId);
 }
@@ -0,0 +1,255 @@
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::registers::{
+    control::{Cr2, Cr3},
+    rflags,
+};
+
+use crate::{debug_print::SUBHEADING, debug_println};
+
+/// Snapshot of the CPU register state, used for diagnostics
+pub struct RegisterDump {
+    rax: u64,
+    rbx: u64,
+    rcx: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rbp: u64,
+    rsp: u64,
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rflags: u64,
+    cr2: u64,
+    cr3: u64,
+}
+
+impl RegisterDump {
+    /// Captures the current register state
+    ///
+    /// This is inlined so that the general purpose registers are captured as close as possible
+    /// to the call site. Note that the register holding the pointer to the capture buffer will
+    /// read back as that pointer instead of its original value
+    #[inline(always)]
+    #[allow(clippy::inline_always, reason = "Needed to capture the caller's registers")]
+    #[allow(clippy::similar_names, reason = "Bindings are named after the registers")]
+    pub fn capture() -> Self {
+        let mut gprs = [0u64; 16];
+
+        // Safety: We only store registers into `gprs`, which is valid for 16 `u64` writes
+        unsafe {
+            asm!(
+                "mov [{0} + 0x00], rax",
+                "mov [{0} + 0x08], rbx",
+                "mov [{0} + 0x10], rcx",
+                "mov [{0} + 0x18], rdx",
+                "mov [{0} + 0x20], rsi",
+                "mov [{0} + 0x28], rdi",
+                "mov [{0} + 0x30], rbp",
+                "mov [{0} + 0x38], rsp",
+                "mov [{0} + 0x40], r8",
+                "mov [{0} + 0x48], r9",
+                "mov [{0} + 0x50], r10",
+                "mov [{0} + 0x58], r11",
+                "mov [{0} + 0x60], r12",
+                "mov [{0} + 0x68], r13",
+                "mov [{0} + 0x70], r14",
+                "mov [{0} + 0x78], r15",
+                in(reg) gprs.as_mut_ptr(),
+                options(nostack, preserves_flags)
+            );
+        }
+
+        let [rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8, r9, r10, r11, r12, r13, r14, r15] = gprs;
+
+        Self {
+            rax,
+            rbx,
+            rcx,
+            rdx,
+            rsi,
+            rdi,
+            rbp,
+            rsp,
+            r8,
+            r9,
+            r10,
+            r11,
+            r12,
+            r13,
+            r14,
+            r15,
+            rflags: rflags::read_raw(),
+            cr2: Cr2::read_raw(),
+            cr3: Cr3::read_raw().0.start_address().as_u64(),
+        }
+    }
+
+    /// Prints the register state to the debug console
+    pub fn print(&self) {
+        debug_println!(SUBHEADING; "RAX: 0x{:016X}  RBX: 0x{:016X}  RCX: 0x{:016X}", self.rax, self.rbx, self.rcx);
+        debug_println!(SUBHEADING; "RDX: 0x{:016X}  RSI: 0x{:016X}  RDI: 0x{:016X}", self.rdx, self.rsi, self.rdi);
+        debug_println!(SUBHEADING; "RBP: 0x{:016X}  RSP: 0x{:016X}  R8:  0x{:016X}", self.rbp, self.rsp, self.r8);
+        debug_println!(SUBHEADING; "R9:  0x{:016X}  R10: 0x{:016X}  R11: 0x{:016X}", self.r9, self.r10, self.r11);
+        debug_println!(SUBHEADING; "R12: 0x{:016X}  R13: 0x{:016X}  R14: 0x{:016X}", self.r12, self.r13, self.r14);
+        debug_println!(SUBHEADING; "R15: 0x{:016X}  RFLAGS: 0x{:016X}", self.r15, self.rflags);
+        debug_println!(SUBHEADING; "CR2: 0x{:016X}  CR3: 0x{:016X}", self.cr2, self.cr3);
+    }
+}
+
+/// Maximum number of frames walked by [`Backtrace`]
+const MAX_BACKTRACE_DEPTH: usize = 32;
+
+/// Top of the kernel stack, set by [`record_stack_top()`]
+static STACK_TOP: AtomicU64 = AtomicU64::new(0);
+
+/// Records the current stack pointer as the top of the kernel stack
+///
+/// This is the upper bound used by [`backtrace()`] to validate frame pointers, so it should be
+/// called at the very start of `_start`
+#[inline(always)]
+#[allow(clippy::inline_always, reason = "Needed to read the caller's stack pointer")]
+pub fn record_stack_top() {
+    let rsp: u64;
+
+    // Safety: Just reads RSP
+    unsafe {
+        asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    }
+
+    STACK_TOP.store(rsp, Ordering::Relaxed);
+}
+
+/// Returns an iterator over the return addresses on the current call stack, innermost first
+///
+/// This walks the frame pointer chain (the kernel is built with `force-frame-pointers`), where
+/// each frame pointer points to the caller's saved frame pointer followed by the return address.
+/// Every frame pointer is checked to lie within the stack before it is dereferenced, and the walk
+/// stops at the first invalid one or after [`MAX_BACKTRACE_DEPTH`] frames
+///
+/// This is inlined so that the walk starts at the caller's frame, which stays live while the
+/// iterator is used
+#[inline(always)]
+#[allow(clippy::inline_always, reason = "Needed to start from the caller's frame")]
+pub fn backtrace() -> Backtrace {
+    let rbp: u64;
+
+    // Safety: Just reads RBP
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+
+    Backtrace {
+        frame_ptr: rbp,
+        stack_bottom: rbp,
+        stack_top: STACK_TOP.load(Ordering::Relaxed),
+        depth: 0,
+    }
+}
+
+/// Iterator over return addresses, see [`backtrace()`]
+pub struct Backtrace {
+    frame_ptr: u64,
+    stack_bottom: u64,
+    stack_top: u64,
+    depth: usize,
+}
+
+impl Iterator for Backtrace {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        // Each frame holds the saved frame pointer and return address (16 bytes), and must be
+        // 8 byte aligned and entirely within the stack. Frames further up the call stack are at
+        // higher addresses, so `stack_bottom` moving up each step guarantees the walk terminates
+        let frame_valid = self.frame_ptr % 8 == 0
+            && self.frame_ptr >= self.stack_bottom
+            && self
+                .frame_ptr
+                .checked_add(16)
+                .is_some_and(|end| end <= self.stack_top);
+
+        if !frame_valid || self.depth == MAX_BACKTRACE_DEPTH {
+            return None;
+        }
+
+        let frame = self.frame_ptr as *const u64;
+
+        // Safety: `frame` was checked to be aligned and within the stack
+        let saved_frame_ptr = unsafe { frame.read() };
+
+        // Safety: Same as above, the return address is the next `u64` in the frame
+        let return_addr = unsafe { frame.wrapping_add(1).read() };
+
+        self.stack_bottom = self.frame_ptr + 16;
+        self.frame_ptr = saved_frame_ptr;
+        self.depth += 1;
+
+        Some(return_addr)
+    }
+}
+
+/// Triggers a breakpoint exception
+///
+/// The breakpoint handler prints the interrupted state, then execution continues normally
+#[allow(dead_code, reason = "Only called while debugging")]
+pub fn breakpoint() {
+    x86_64::instructions::interrupts::int3();
+}
+
+/// Deliberately panics, to exercise the panic path
+#[cfg(feature = "test_panic")]
+pub fn test_panic() {
+    panic!("Test panic triggered by `debug::test_panic()`");
+}
+
+/// Asserts that a condition holds
+///
+/// On failure the panic message contains the condition's source text and the given context
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr, $($context:tt)+) => {
+        if !$cond {
+            panic!("assertion failed: `{}`: {}", stringify!($cond), format_args!($($context)+));
+        }
+    };
+}
+
+/// Asserts that two expressions are equal
+///
+/// On failure the panic message contains both expressions' source text and values, and the given context
+#[macro_export]
+macro_rules! kassert_eq {
+    ($left:expr, $right:expr, $($context:tt)+) => {{
+        let left = &$left;
+        let right = &$right;
+
+        if *left != *right {
+            panic!(
+                "assertion failed: `{} == {}`: {}\n  left: {:?}\n right: {:?}",
+                stringify!($left),
+                stringify!($right),
+                format_args!($($context)+),
+                left,
+                right,
+            );
+        }
+    }};
+}
+
+/// Same as [`kassert!`], but only checked in debug builds
+#[macro_export]
+macro_rules! debug_kassert {
+    ($($arg:tt)+) => {
+        if cfg!(debug_assertions) {
+            $crate::kassert!($($arg)+);
+        }
+    };
+}
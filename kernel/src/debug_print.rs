@@ -1,23 +1,239 @@
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
 use limine::framebuffer::{Framebuffer, MemoryModel};
 use spinning_top::Spinlock;
 
-use crate::FRAMEBUFFER_REQUEST;
+use crate::{
+    boot::{self, Phase},
+    serial,
+    sync::Once,
+    FRAMEBUFFER_REQUEST,
+};
 
 pub const HEADING: &str = "[kernel] ";
 pub const SUBHEADING: &str = "       - ";
 
+/// Color that prefixes like [`HEADING`] are printed in
+pub const PREFIX_COLOR: Color = Color::new(0x5F, 0xAF, 0xFF);
+
+/// Log message severity, in increasing order
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[allow(dead_code, reason = "Not all levels are used yet")]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    /// Tag printed before messages of this level, padded to the same width for every level
+    const fn tag(self) -> &'static str {
+        match self {
+            Self::Trace => "[trace] ",
+            Self::Debug => "[debug] ",
+            Self::Info => "[info]  ",
+            Self::Warn => "[warn]  ",
+            Self::Error => "[error] ",
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Self::Trace => Color::new(0x80, 0x80, 0x80),
+            Self::Debug => Color::new(0x5F, 0xD7, 0xD7),
+            Self::Info => Color::new(0x5F, 0xD7, 0x5F),
+            Self::Warn => Color::new(0xFF, 0xD7, 0x00),
+            Self::Error => Color::new(0xFF, 0x5F, 0x5F),
+        }
+    }
+}
+
+/// Minimum level of messages that get printed, messages below it are dropped
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Sets the minimum level of messages that get printed
+#[allow(dead_code, reason = "Nothing changes the level yet")]
+pub fn set_level(level: Level) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns `level` if messages of that level should be printed, or `None` if they're dropped
+pub fn enabled_level(level: Level) -> Option<Level> {
+    is_at_least(level, LOG_LEVEL.load(Ordering::Relaxed)).then_some(level)
+}
+
+/// Whether `level` is at or above the `threshold` level index
+const fn is_at_least(level: Level, threshold: u8) -> bool {
+    level as u8 >= threshold
+}
+
+// Messages at or above the threshold are printed, anything below it is dropped
+const _: () = assert!(is_at_least(Level::Info, Level::Info as u8));
+const _: () = assert!(is_at_least(Level::Error, Level::Info as u8));
+const _: () = assert!(!is_at_least(Level::Debug, Level::Info as u8));
+const _: () = assert!(is_at_least(Level::Trace, Level::Trace as u8));
+
+// Every tag has the same width, so messages line up
+const _: () = {
+    let width = Level::Trace.tag().len();
+    assert!(Level::Debug.tag().len() == width);
+    assert!(Level::Info.tag().len() == width);
+    assert!(Level::Warn.tag().len() == width);
+    assert!(Level::Error.tag().len() == width);
+};
+
+/// Foreground text color
+#[derive(Clone, Copy)]
+pub struct Color {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl Color {
+    pub const WHITE: Self = Self::new(0xFF, 0xFF, 0xFF);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// Tints a glyph's `coverage` value by a color channel, where full coverage gives `channel` as is
+fn scale_channel(coverage: u8, channel: u8) -> u8 {
+    let scaled = u16::from(coverage) * u16::from(channel) / u16::from(u8::MAX);
+
+    #[allow(clippy::cast_possible_truncation, reason = "`scaled` is at most `u8::MAX`")]
+    let scaled = scaled as u8;
+
+    scaled
+}
+
 include!(concat!(env!("OUT_DIR"), "/console_font.rs"));
 
+/// Tab stops are placed every `TAB_WIDTH` columns
+const TAB_WIDTH: u64 = 4;
+
+/// Column of the first tab stop after `cursor_x`
+const fn next_tab_stop(cursor_x: u64) -> u64 {
+    (cursor_x / TAB_WIDTH + 1) * TAB_WIDTH
+}
+
+// A tab always moves forward, to the next multiple of `TAB_WIDTH`
+const _: () = assert!(next_tab_stop(0) == 4);
+const _: () = assert!(next_tab_stop(3) == 4);
+const _: () = assert!(next_tab_stop(4) == 8);
+
+/// Index of `c`'s glyph in [`GLYPHS`], or `None` if the font has no glyph for it
+///
+/// The font contains the contiguous `FIRST_CHAR..=LAST_CHAR` range, see `build.rs`
+const fn glyph_index(c: char) -> Option<usize> {
+    if c >= FIRST_CHAR && c <= LAST_CHAR {
+        Some(c as usize - FIRST_CHAR as usize)
+    } else {
+        None
+    }
+}
+
+// Printable ASCII maps onto the font's glyphs in order, everything else has no glyph
+const _: () = assert!(matches!(glyph_index('!'), Some(0)));
+const _: () = assert!(matches!(glyph_index('A'), Some(32)));
+const _: () = assert!(matches!(glyph_index('~'), Some(93)));
+const _: () = assert!(glyph_index(' ').is_none());
+const _: () = assert!(glyph_index('\x7F').is_none());
+const _: () = assert!(glyph_index('\u{E9}').is_none());
+const _: () = assert!(glyph_index('\u{2588}').is_none());
+
+/// Glyph for `c`, or [`REPLACEMENT_GLYPH`] if the font doesn't have one (e.g. non-ASCII characters)
+fn glyph(c: char) -> Glyph {
+    glyph_index(c)
+        .and_then(|idx| GLYPHS.get(idx))
+        .copied()
+        .unwrap_or(REPLACEMENT_GLYPH)
+}
+
+/// Layout of a pixel in the framebuffer
+#[derive(Clone, Copy)]
+struct PixelFormat {
+    bytes_per_pixel: u64,
+    red_shift: u8,
+    red_size: u8,
+    green_shift: u8,
+    green_size: u8,
+    blue_shift: u8,
+    blue_size: u8,
+}
+
+impl PixelFormat {
+    /// Packs an 8 bit per channel color into a pixel value, which takes up the low
+    /// `bytes_per_pixel` bytes of the result in little endian order
+    const fn pack(self, r: u8, g: u8, b: u8) -> u32 {
+        Self::channel(r, self.red_shift, self.red_size)
+            | Self::channel(g, self.green_shift, self.green_size)
+            | Self::channel(b, self.blue_shift, self.blue_size)
+    }
+
+    /// Places an 8 bit channel `value` into a `size` bit field at `shift`
+    ///
+    /// Channels narrower than 8 bits keep just the most significant bits
+    const fn channel(value: u8, shift: u8, size: u8) -> u32 {
+        (value as u32 >> 8_u8.saturating_sub(size)) << shift
+    }
+}
+
+// Channels land at their shifts, and channels narrower than 8 bits keep their most significant bits
+const _: () = {
+    // 32 bit XRGB, the common case
+    let xrgb_8888 = PixelFormat {
+        bytes_per_pixel: 4,
+        red_shift: 16,
+        red_size: 8,
+        green_shift: 8,
+        green_size: 8,
+        blue_shift: 0,
+        blue_size: 8,
+    };
+    assert!(xrgb_8888.pack(0x12, 0x34, 0x56) == 0x0012_3456);
+
+    // 24 bit BGR, channel order flipped
+    let bgr_888 = PixelFormat {
+        bytes_per_pixel: 3,
+        red_shift: 0,
+        red_size: 8,
+        green_shift: 8,
+        green_size: 8,
+        blue_shift: 16,
+        blue_size: 8,
+    };
+    assert!(bgr_888.pack(0x12, 0x34, 0x56) == 0x0056_3412);
+
+    // 16 bit RGB 565
+    let rgb_565 = PixelFormat {
+        bytes_per_pixel: 2,
+        red_shift: 11,
+        red_size: 5,
+        green_shift: 5,
+        green_size: 6,
+        blue_shift: 0,
+        blue_size: 5,
+    };
+    assert!(rgb_565.pack(0xFF, 0xFF, 0xFF) == 0xFFFF);
+    assert!(rgb_565.pack(0x80, 0x80, 0x80) == 0x8410);
+};
+
 struct DebugPrinter {
     framebuf_addr: *mut u8,
     framebuf_width: u64,
     framebuf_height: u64,
     framebuf_pitch: u64,
-    framebuf_red_shift: u8,
-    framebuf_green_shift: u8,
-    framebuf_blue_shift: u8,
+    framebuf_format: PixelFormat,
     cursor_x: u64,
     cursor_y: u64,
+    color: Color,
 }
 
 // Safety: framebuf_addr is just a simple raw pointer and can be used by all threads
@@ -25,8 +241,18 @@ unsafe impl Send for DebugPrinter {}
 
 impl DebugPrinter {
     pub fn new() -> Option<Self> {
-        // We only support 32 bit RGB framebuffers
-        let framebuf_filter = |framebuf: &Framebuffer| framebuf.memory_model() == MemoryModel::RGB && framebuf.bpp() == 32;
+        // We only support 24 and 32 bit RGB framebuffers
+        //
+        // Lines may be padded so the pitch can be larger than a line's pixels, but if it's smaller
+        // lines would overlap and the framebuffer info can't be trusted
+        //
+        // 32 bit pixels are written as a single `u32`, so they have to be `u32` aligned
+        let framebuf_filter = |framebuf: &Framebuffer| {
+            framebuf.memory_model() == MemoryModel::RGB
+                && matches!(framebuf.bpp(), 24 | 32)
+                && framebuf.pitch() >= framebuf.width() * u64::from(framebuf.bpp() / 8)
+                && (framebuf.bpp() != 32 || (framebuf.addr().addr() % 4 == 0 && framebuf.pitch() % 4 == 0))
+        };
 
         // Find the first framebuffer that matches our condition
         // If theres no response or suitable framebuffer we just return `None` and
@@ -43,25 +269,25 @@ impl DebugPrinter {
         let framebuf_width = framebuf.width();
         let framebuf_height = framebuf.height();
         let framebuf_pitch = framebuf.pitch();
-        let framebuf_red_shift = framebuf.red_mask_shift();
-        let framebuf_green_shift = framebuf.green_mask_shift();
-        let framebuf_blue_shift = framebuf.blue_mask_shift();
-
-        // Sanity test that framebuffer addr is u32 aligned
-        if framebuf_addr as usize % 4 != 0 {
-            return None;
-        }
+        let framebuf_format = PixelFormat {
+            bytes_per_pixel: u64::from(framebuf.bpp() / 8),
+            red_shift: framebuf.red_mask_shift(),
+            red_size: framebuf.red_mask_size(),
+            green_shift: framebuf.green_mask_shift(),
+            green_size: framebuf.green_mask_size(),
+            blue_shift: framebuf.blue_mask_shift(),
+            blue_size: framebuf.blue_mask_size(),
+        };
 
         Some(Self {
             framebuf_addr,
             framebuf_width,
             framebuf_height,
             framebuf_pitch,
-            framebuf_red_shift,
-            framebuf_green_shift,
-            framebuf_blue_shift,
+            framebuf_format,
             cursor_x: 0,
             cursor_y: 0,
+            color: Color::WHITE,
         })
     }
 
@@ -72,26 +298,44 @@ impl DebugPrinter {
 
             // Tab
             '\t' => {
-                for _ in 0..4 {
-                    self.print_char(' ');
+                // Move to the next tab stop, or go to new line if it's past the end of the screen
+                // Like after a space, the cursor may end up just past the last column
+                let tab_stop = next_tab_stop(self.cursor_x);
+
+                if self.column_fits(tab_stop - 1) {
+                    self.cursor_x = tab_stop;
+                } else {
+                    self.new_line();
+                }
+            }
+
+            // Backspace
+            '\x08' => {
+                // Move back a column and blank it, unless we're already at the start of the line
+                if self.cursor_x > 0 {
+                    self.cursor_x -= 1;
+                    self.clear_cell();
                 }
             }
 
             // Space
             ' ' => {
-                // If the cursor is past the end of the screen go to new line
-                // else just move to the next column
-                if self.cursor_x == self.framebuffer_width_chars() {
-                    self.new_line();
-                } else {
+                // If the cursor is within the screen just move to the next column
+                // else go to new line
+                if self.column_fits(self.cursor_x) {
                     self.cursor_x += 1;
+                } else {
+                    self.new_line();
                 }
             }
 
+            // Any other control characters have nothing to draw, so skip them
+            c if c.is_control() => {}
+
             // Regular character
             c => {
                 // If the cursor is past the end of the screen go to new line
-                if self.cursor_x == self.framebuffer_width_chars() {
+                if !self.column_fits(self.cursor_x) {
                     self.new_line();
                 }
 
@@ -100,9 +344,7 @@ impl DebugPrinter {
                 let y_offset = self.cursor_y * CHAR_HEIGHT;
 
                 // Glyph coverage bitmap for this character
-                let glyph = GLYPHS
-                    .get(c as usize - '!' as usize)
-                    .expect("Character outside of ASCII range");
+                let glyph = glyph(c);
 
                 // Draw the character
                 for y in 0..CHAR_HEIGHT {
@@ -111,7 +353,11 @@ impl DebugPrinter {
                         #[allow(clippy::indexing_slicing, reason = "x/y will always be in CHAR_WIDTH/CHAR_HEIGHT range")]
                         let coverage = glyph[y as usize][x as usize];
 
-                        self.draw_pixel(x_offset + x, y_offset + y, coverage, coverage, coverage);
+                        let r = scale_channel(coverage, self.color.r);
+                        let g = scale_channel(coverage, self.color.g);
+                        let b = scale_channel(coverage, self.color.b);
+
+                        self.draw_pixel(x_offset + x, y_offset + y, r, g, b);
                     }
                 }
 
@@ -121,14 +367,30 @@ impl DebugPrinter {
         }
     }
 
-    /// Framebuffer width in characters
-    fn framebuffer_width_chars(&self) -> u64 {
-        self.framebuf_width / CHAR_WIDTH
+    /// Blanks the character cell at the cursor
+    fn clear_cell(&self) {
+        // Pixel position of the top left of the cell
+        let x_offset = self.cursor_x * CHAR_WIDTH;
+        let y_offset = self.cursor_y * CHAR_HEIGHT;
+
+        for y in 0..CHAR_HEIGHT {
+            for x in 0..CHAR_WIDTH {
+                self.draw_pixel(x_offset + x, y_offset + y, 0, 0, 0);
+            }
+        }
     }
 
-    /// Framebuffer height in characters
-    fn framebuffer_height_chars(&self) -> u64 {
-        self.framebuf_height / CHAR_HEIGHT
+    /// Whether a character in `column` lies entirely within the framebuffer
+    ///
+    /// The framebuffer width doesn't have to be a multiple of `CHAR_WIDTH`, in which case the
+    /// partial column at the right edge is left empty
+    fn column_fits(&self, column: u64) -> bool {
+        (column + 1) * CHAR_WIDTH <= self.framebuf_width
+    }
+
+    /// Whether a character in `row` lies entirely within the framebuffer, see [`Self::column_fits()`]
+    fn row_fits(&self, row: u64) -> bool {
+        (row + 1) * CHAR_HEIGHT <= self.framebuf_height
     }
 
     #[allow(clippy::many_single_char_names, reason = "Variable meanings are obvious")]
@@ -137,96 +399,150 @@ impl DebugPrinter {
         assert!(x < self.framebuf_width, "x outside of framebuffer bounds");
         assert!(y < self.framebuf_height, "y outside of framebuffer bounds");
 
-        // x * 4 because 32 bit RGB has 4 bytes per pixel
-        let offset = (x * 4) + (y * self.framebuf_pitch);
+        let bytes_per_pixel = self.framebuf_format.bytes_per_pixel;
+        let offset = (x * bytes_per_pixel) + (y * self.framebuf_pitch);
 
         #[allow(clippy::cast_possible_truncation, reason = "usize and u64 have same size here")]
         let offset = offset as usize;
 
-        let color =
-            (u32::from(r) << self.framebuf_red_shift) | (u32::from(g) << self.framebuf_green_shift) | (u32::from(b) << self.framebuf_blue_shift);
+        let color = self.framebuf_format.pack(r, g, b);
 
         // Safety: This offset pointer is guaranteed to be within the framebuffer bounds
         // because x/y are within the width/height range and we trust that limine has
         // given us correct framebuffer info overall
         let ptr = unsafe { self.framebuf_addr.add(offset) };
 
-        #[allow(clippy::cast_ptr_alignment, reason = "ptr was tested to have u32 alignment in `new()`")]
-        let ptr = ptr.cast::<u32>();
+        // 32 bit pixels take a single write, which matters since clearing and scrolling go through here
+        if bytes_per_pixel == 4 {
+            #[allow(clippy::cast_ptr_alignment, reason = "ptr was tested to have u32 alignment in `new()`")]
+            let ptr = ptr.cast::<u32>();
 
-        // Safety: ptr is a valid pointer within the framebuffer
-        unsafe {
-            ptr.write_volatile(color);
+            // Safety: ptr is a valid pointer within the framebuffer
+            unsafe {
+                ptr.write_volatile(color);
+            }
+
+            return;
+        }
+
+        // 24 bit pixels aren't u32 aligned (or u32 sized), so write them a byte at a time
+        #[allow(clippy::cast_possible_truncation, reason = "usize and u64 have same size here")]
+        let pixel_bytes = color.to_le_bytes().into_iter().take(bytes_per_pixel as usize);
+
+        for (i, byte) in pixel_bytes.enumerate() {
+            // Safety: `i` is less than `bytes_per_pixel` so this is still within the pixel
+            let byte_ptr = unsafe { ptr.add(i) };
+
+            // Safety: `byte_ptr` is a valid pointer within the framebuffer
+            unsafe {
+                byte_ptr.write_volatile(byte);
+            }
         }
     }
 
     fn new_line(&mut self) {
         // If we're at the last row scroll the screen, else just go to the next row
-        if self.cursor_y == self.framebuffer_height_chars() - 1 {
-            self.scroll();
-        } else {
+        if self.row_fits(self.cursor_y + 1) {
             self.cursor_y += 1;
+        } else {
+            self.scroll();
         }
 
-        // Go back to the start of the line as well
+        // Go back to the start of the line as well, and reset the color
         self.cursor_x = 0;
+        self.color = Color::WHITE;
     }
 
-    /// Scrolls the screen downards by one row
-    fn scroll(&self) {
-        // Returns a slice representing a horizontal line at coordinate `y` in the framebuffer
-        let line = |y: u64| {
-            assert!(y < self.framebuf_height, "y outside of framebuffer bounds");
+    /// Returns a slice over the framebuffer lines (rows of pixels) in `ys`, including any padding
+    /// at the end of each line
+    fn lines(&mut self, ys: Range<u64>) -> &mut [u8] {
+        assert!(ys.start <= ys.end && ys.end <= self.framebuf_height, "ys outside of framebuffer bounds");
 
-            #[allow(clippy::cast_possible_truncation, reason = "usize and u64 have same size here")]
-            let offset = (y * self.framebuf_pitch) as usize;
+        #[allow(clippy::cast_possible_truncation, reason = "usize and u64 have same size here")]
+        let offset = (ys.start * self.framebuf_pitch) as usize;
 
-            // Safety: This offset pointer is guaranteed to be within the framebuffer bounds
-            // because `y` is in the height range
-            let ptr = unsafe { self.framebuf_addr.add(offset) };
+        #[allow(clippy::cast_possible_truncation, reason = "usize and u64 have same size here")]
+        let len = ((ys.end - ys.start) * self.framebuf_pitch) as usize;
 
-            // Length of the slice, * 4 because we have 4 bytes per pixel
-            #[allow(clippy::cast_possible_truncation, reason = "usize and u64 have same size here")]
-            let len = self.framebuf_width as usize * 4;
+        // Safety: This offset pointer is guaranteed to be within the framebuffer bounds
+        // because `ys` is in the height range
+        let ptr = unsafe { self.framebuf_addr.add(offset) };
 
-            // Safety: `ptr` is a valid pointer to the start of a line with length `len`
-            unsafe { core::slice::from_raw_parts_mut(ptr, len) }
-        };
+        // Safety: `ptr` is a valid pointer to the start of a run of lines with total length `len`,
+        // and we hold `&mut self` so nothing else can access the framebuffer while the slice lives
+        unsafe { core::slice::from_raw_parts_mut(ptr, len) }
+    }
 
-        // Go over every line (excluding the last row) and copy the corresponding line in the next row into it
-        for y in 0..(self.framebuf_height - CHAR_HEIGHT) {
-            let src_line = line(y);
-            let dst_line = line(y + CHAR_HEIGHT);
+    /// Clears the screen and moves the cursor back to the top left
+    fn clear(&mut self) {
+        let height = self.framebuf_height;
+        self.lines(0..height).fill(0);
 
-            src_line.copy_from_slice(dst_line);
-        }
+        self.cursor_x = 0;
+        self.cursor_y = 0;
+    }
+
+    /// Scrolls the screen contents up by one row, leaving the last row blank
+    fn scroll(&mut self) {
+        let height = self.framebuf_height;
+
+        #[allow(clippy::cast_possible_truncation, reason = "usize and u64 have same size here")]
+        let row_len = (CHAR_HEIGHT * self.framebuf_pitch) as usize;
+
+        // Move everything below the first row up by one row in a single copy
+        // The whole pitch of each line is copied, so any padding at the end of lines is moved along
+        // with the pixels, which is harmless since it's never displayed
+        let screen = self.lines(0..height);
+        screen.copy_within(row_len.., 0);
+
+        // Blank the last row
+        self.lines((height - CHAR_HEIGHT)..height).fill(0);
+    }
+}
 
-        // Go over every line in the last row and zero it
-        for y in (self.framebuf_height - CHAR_HEIGHT)..self.framebuf_height {
-            line(y).fill(0);
+impl core::fmt::Write for DebugPrinter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            self.print_char(c);
         }
+
+        Ok(())
     }
 }
 
-static DEBUG_PRINTER: Spinlock<Option<DebugPrinter>> = Spinlock::new(None);
+// Holds `None` if no suitable framebuffer was found
+static DEBUG_PRINTER: Once<Option<Spinlock<DebugPrinter>>> = Once::new();
 
 pub fn init() {
-    *DEBUG_PRINTER.lock() = DebugPrinter::new();
+    DEBUG_PRINTER.call_once(|| DebugPrinter::new().map(Spinlock::new));
+    boot::complete(Phase::DebugPrint);
+}
+
+/// Returns whether [`init()`] has completed
+pub fn is_initialized() -> bool {
+    DEBUG_PRINTER.get().is_some()
+}
+
+/// Runs `f` on the debug printer, returns `None` if there's no printer
+///
+/// Once the kernel has panicked the printer is only used if it isn't locked, since the code that
+/// panicked may be holding the lock. Its output then only goes to serial
+fn with_printer<T>(f: impl FnOnce(&mut DebugPrinter) -> T) -> Option<T> {
+    let printer = DEBUG_PRINTER.get().and_then(Option::as_ref)?;
+
+    let mut printer = if serial::in_panic_mode() { printer.try_lock()? } else { printer.lock() };
+
+    Some(f(&mut printer))
 }
 
 pub struct Helper;
 
 impl core::fmt::Write for Helper {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        let mut printer = DEBUG_PRINTER.lock();
-
-        if let Some(printer) = printer.as_mut() {
-            for c in s.chars() {
-                printer.print_char(c);
-            }
-        }
+        // Serial output works even if there's no framebuffer, or before `init()` has been called
+        serial::write_str(s);
 
-        Ok(())
+        with_printer(|printer| printer.write_str(s)).unwrap_or(Ok(()))
     }
 }
 
@@ -234,10 +550,64 @@ pub fn helper(args: core::fmt::Arguments) {
     _ = core::fmt::write(&mut Helper, args);
 }
 
+/// Same as [`helper()`] but prints in `color`, then resets the color to white
+///
+/// Color only applies to the framebuffer, serial output is left as is
+pub fn helper_colored(color: Color, args: core::fmt::Arguments) {
+    set_color(color);
+    helper(args);
+    set_color(Color::WHITE);
+}
+
+/// Clears the screen, does nothing if there's no framebuffer
+pub fn clear() {
+    with_printer(DebugPrinter::clear);
+}
+
+/// Prints `prefix` in [`PREFIX_COLOR`], followed by `args`
+pub fn helper_prefixed(prefix: &str, args: core::fmt::Arguments) {
+    helper_colored(PREFIX_COLOR, format_args!("{prefix}"));
+    helper(args);
+}
+
+/// Prints the tag for `level` in its color, followed by `args`
+///
+/// Doesn't check if `level` is enabled, that's done by the macros so that disabled messages
+/// aren't formatted at all
+pub fn helper_leveled(level: Level, args: core::fmt::Arguments) {
+    helper_colored(level.color(), format_args!("{}", level.tag()));
+    helper(args);
+}
+
+fn set_color(color: Color) {
+    with_printer(|printer| printer.color = color);
+}
+
+/// Prints directly to the framebuffer without taking any locks
+///
+/// This sets up a temporary printer straight from the bootloader's framebuffer info, so it works
+/// even if `DEBUG_PRINTER` is locked or uninitialized. The output starts at the top left of the
+/// screen, drawing over whatever is there. Only meant for the panic path, after
+/// [`serial::enter_panic_mode()`] so that the serial copy doesn't take any locks either
+pub fn emergency_print(args: core::fmt::Arguments) {
+    serial::write_fmt(args);
+
+    if let Some(mut printer) = DebugPrinter::new() {
+        _ = core::fmt::write(&mut printer, args);
+    }
+}
+
 #[macro_export]
 macro_rules! debug_print {
+    // `$level` is only evaluated once, and the message isn't formatted at all if it's dropped
+    (level: $level:expr; $($arg:tt)*) => {
+        if let Some(level) = $crate::debug_print::enabled_level($level) {
+            $crate::debug_print::helper_leveled(level, format_args!($($arg)*))
+        }
+    };
+
     ($prefix:expr; $($arg:tt)*) => {
-        $crate::debug_print!("{}{}", $prefix, format_args!($($arg)*));
+        $crate::debug_print::helper_prefixed($prefix, format_args!($($arg)*))
     };
 
     ($($arg:tt)*) => {
@@ -251,11 +621,22 @@ macro_rules! debug_println {
         $crate::debug_println!("")
     };
 
+    (level: $level:expr; $($arg:tt)*) => {
+        $crate::debug_print!(level: $level; "{}\n", format_args!($($arg)*))
+    };
+
     ($prefix:expr; $($arg:tt)*) => {
-        $crate::debug_print!("{}{}\n", $prefix, format_args!($($arg)*))
+        $crate::debug_print!($prefix; "{}\n", format_args!($($arg)*))
     };
 
     ($($arg:tt)*) => {
         $crate::debug_print!("{}\n", format_args!($($arg)*))
     };
 }
+
+#[macro_export]
+macro_rules! debug_print_colored {
+    ($color:expr; $($arg:tt)*) => {
+        $crate::debug_print::helper_colored($color, format_args!($($arg)*))
+    };
+}
@@ -0,0 +1,121 @@
+use x86_64::{
+    instructions::{
+        segmentation::{Segment, CS, DS, ES, SS},
+        tables::load_tss,
+    },
+    structures::{
+        gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+        tss::TaskStateSegment,
+    },
+    VirtAddr,
+};
+
+use crate::{
+    boot::{self, Phase},
+    debug_print::HEADING,
+    debug_println,
+    sync::Once,
+};
+
+/// Size of each of the kernel's interrupt stacks
+const STACK_SIZE: usize = 4096 * 5;
+
+/// Index of the interrupt stack table entry the double fault handler runs on
+///
+/// Double faults get their own stack so that we can still print diagnostics if the fault was
+/// caused by the kernel stack overflowing or getting corrupted
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+#[repr(align(16))]
+#[allow(dead_code, reason = "Stack memory is only accessed by the CPU")]
+struct Stack([u8; STACK_SIZE]);
+
+/// Stack the CPU switches to (via RSP0) when an interrupt arrives while running in ring 3
+static mut PRIVILEGE_STACK: Stack = Stack([0; STACK_SIZE]);
+
+/// Stack the double fault handler runs on
+static mut DOUBLE_FAULT_STACK: Stack = Stack([0; STACK_SIZE]);
+
+/// Address of the top of `stack`, since stacks grow downwards
+fn stack_top(stack: *mut Stack) -> VirtAddr {
+    VirtAddr::from_ptr(stack.wrapping_add(1))
+}
+
+struct Gdt {
+    table: GlobalDescriptorTable,
+    code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+    tss_selector: SegmentSelector,
+}
+
+static TSS: Once<TaskStateSegment> = Once::new();
+static GDT: Once<Gdt> = Once::new();
+
+/// Sets up and loads the kernel's own GDT and TSS, replacing the bootloader's GDT
+///
+/// This has to run before [`crate::interrupt::init()`], since the IDT refers to the TSS's
+/// interrupt stacks
+pub fn init() {
+    debug_println!(HEADING; "Setting up GDT and TSS");
+
+    // The TSS has to be fully set up before its descriptor is created, as the descriptor needs
+    // a `'static` reference to it
+    let tss = TSS.call_once(|| {
+        let mut tss = TaskStateSegment::new();
+        tss.privilege_stack_table[0] = stack_top(core::ptr::addr_of_mut!(PRIVILEGE_STACK));
+
+        // The TSS is packed so we can't borrow into its fields, instead modify a copy of the IST and write it back
+        let mut ist = tss.interrupt_stack_table;
+        let double_fault_stack = ist
+            .get_mut(usize::from(DOUBLE_FAULT_IST_INDEX))
+            .expect("Invalid double fault IST index");
+        *double_fault_stack = stack_top(core::ptr::addr_of_mut!(DOUBLE_FAULT_STACK));
+        tss.interrupt_stack_table = ist;
+
+        tss
+    });
+
+    let gdt = GDT.call_once(|| {
+        let mut gdt = GlobalDescriptorTable::new();
+        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+
+        Gdt {
+            table: gdt,
+            code_selector,
+            data_selector,
+            tss_selector,
+        }
+    });
+
+    gdt.table.load();
+
+    // Safety: The selector points to the kernel code segment in the GDT we just loaded
+    unsafe {
+        CS::set_reg(gdt.code_selector);
+    }
+
+    // Safety: The selector points to the kernel data segment in the GDT we just loaded
+    unsafe {
+        SS::set_reg(gdt.data_selector);
+    }
+
+    // Safety: Same as above
+    unsafe {
+        DS::set_reg(gdt.data_selector);
+    }
+
+    // Safety: Same as above
+    unsafe {
+        ES::set_reg(gdt.data_selector);
+    }
+
+    // Safety: The selector points to a valid TSS descriptor in the GDT we just loaded, and the
+    // TSS is a static so it lives forever
+    unsafe {
+        load_tss(gdt.tss_selector);
+    }
+
+    boot::complete(Phase::Gdt);
+}
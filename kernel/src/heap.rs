@@ -3,9 +3,12 @@ use core::ptr::NonNull;
 use spinning_top::Spinlock;
 
 use crate::{
+    boot::{self, Phase},
+    debug_kassert,
     debug_print::{HEADING, SUBHEADING},
-    debug_println,
+    debug_println, kassert, kassert_eq,
     page_alloc::{LARGE_PAGE_SIZE, SMALL_PAGE_SIZE},
+    sync::Once,
 };
 
 const CHUNK_ALIGN: usize = LARGE_PAGE_SIZE; // = 0x200_000
@@ -16,6 +19,48 @@ pub const SLOT_SIZE: usize = SMALL_PAGE_SIZE; // = 0x1000
 
 const SLOTS_PER_CHUNK: usize = 512 - 2;
 
+/// Metadata the heap keeps for each slot on behalf of the slot's owner
+///
+/// The heap never interprets it, it's up to the owner (e.g. an [`crate::arena::Arena`]) to use it
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SlotMetadata {
+    /// Number of objects the owner has allocated from the slot
+    pub alloc_count: u64,
+
+    /// Address of the next slot in the owner's list of slots, or 0 if there is none
+    pub next_slot: u64,
+}
+
+impl SlotMetadata {
+    const EMPTY: Self = Self {
+        alloc_count: 0,
+        next_slot: 0,
+    };
+}
+
+// Same layout as the `(u64, u64)` this used to be, so the chunk header layout is unchanged
+const _: () = assert!(core::mem::size_of::<SlotMetadata>() == 16);
+const _: () = assert!(core::mem::offset_of!(SlotMetadata, alloc_count) == 0 && core::mem::offset_of!(SlotMetadata, next_slot) == 8);
+
+// Round trip `SlotMetadata` through its raw in-chunk representation, checking that the fields land
+// in the expected words and come back unchanged
+const _: () = {
+    let metadata = SlotMetadata {
+        alloc_count: 0x1234,
+        next_slot: 0xDEAD_B000,
+    };
+
+    // Safety: `SlotMetadata` is `repr(C)` with two `u64` fields and no padding, as checked above
+    let raw: [u64; 2] = unsafe { core::mem::transmute(metadata) };
+    let [alloc_count, next_slot] = raw;
+    assert!(alloc_count == 0x1234 && next_slot == 0xDEAD_B000);
+
+    // Safety: Same as above, and any pair of `u64`s is a valid `SlotMetadata`
+    let metadata: SlotMetadata = unsafe { core::mem::transmute(raw) };
+    assert!(metadata.alloc_count == 0x1234 && metadata.next_slot == 0xDEAD_B000);
+};
+
 /// Header placed at the start of each heap chunk
 ///
 /// This occupied the first 2 slots in the chunk
@@ -30,18 +75,20 @@ const SLOTS_PER_CHUNK: usize = 512 - 2;
 struct ChunkHeader {
     num_alloc_slots: usize,
     unmapped_area_node: UnmappedAreaNode,
-    slot_metadatas: [(u64, u64); 510],
+    slot_metadatas: [SlotMetadata; 510],
 }
 
-impl ChunkHeader {
-    const _SIZE_CHECK: () = assert!(core::mem::size_of::<Self>() <= 2 * SLOT_SIZE);
-    const _ALIGN_CHECK: () = assert!(core::mem::align_of::<Self>() == CHUNK_ALIGN);
+// The header's fields have to fit in the first 2 slots of the chunk. `size_of()` can't be used for
+// this, since it's padded up to the chunk alignment, so check where the last field ends instead
+const _: () = assert!(core::mem::offset_of!(ChunkHeader, slot_metadatas) + core::mem::size_of::<[SlotMetadata; 510]>() <= 2 * SLOT_SIZE);
+const _: () = assert!(core::mem::align_of::<ChunkHeader>() == CHUNK_ALIGN);
 
+impl ChunkHeader {
     /// Get the pointer to a slot within this chunk
     fn slot_ptr(&self, slot_idx: usize) -> NonNull<u8> {
         // First 2 slots are occupied by the header, so `slot_idx` needs to be
         // shifted up by 2 to get the absolute index
-        assert!(slot_idx < SLOTS_PER_CHUNK);
+        kassert!(slot_idx < SLOTS_PER_CHUNK, "slot index {slot_idx} out of range");
         let abs_slot_idx = slot_idx + 2;
 
         // Calculate the slot address from this header's address
@@ -64,9 +111,7 @@ struct FreeSlotHeader {
     next_free: Option<NonNull<Self>>,
 }
 
-impl FreeSlotHeader {
-    const _ALIGN_CHECK: () = assert!(core::mem::align_of::<Self>() == SLOT_ALIGN);
-}
+const _: () = assert!(core::mem::align_of::<FreeSlotHeader>() == SLOT_ALIGN);
 
 // Bootstrap heap chunk space reserved in the BSS section (see linker script)
 extern "C" {
@@ -81,18 +126,51 @@ struct HeapAlloc {
 
 unsafe impl Send for HeapAlloc {}
 
-static HEAP_ALLOC: Spinlock<Option<HeapAlloc>> = Spinlock::new(None);
+impl HeapAlloc {
+    /// Returns whether `slot_ptr` is on the free slot list
+    ///
+    /// This walks the whole list, so it takes time proportional to the number of free slots
+    fn is_free(&self, slot_ptr: NonNull<u8>) -> bool {
+        let mut next = self.free_slot_list;
+
+        while let Some(free_slot) = next {
+            if free_slot.cast::<u8>() == slot_ptr {
+                return true;
+            }
+
+            // Safety: Every slot on the free list starts with a valid `FreeSlotHeader`, and the list
+            // can't change while we're borrowing `HEAP_ALLOC`
+            next = unsafe { free_slot.as_ref() }.next_free;
+        }
+
+        false
+    }
+}
+
+static HEAP_ALLOC: Once<Spinlock<HeapAlloc>> = Once::new();
 
 pub fn init() {
-    // Set up the bootstrap heap chunk
     debug_println!(HEADING; "Initializing kernel heap");
 
+    // All of the setup happens inside `call_once()`, so if this is called twice it panics before the
+    // bootstrap chunk (which is in use by then) gets overwritten
+    HEAP_ALLOC.call_once(init_boot_chunk);
+
+    boot::complete(Phase::Heap);
+}
+
+/// Sets up the bootstrap heap chunk and returns an allocator that hands out its slots
+fn init_boot_chunk() -> Spinlock<HeapAlloc> {
     let boot_chunk_start = core::ptr::addr_of_mut!(BOOTSTRAP_HEAP_CHUNK_START);
     let boot_chunk_end = core::ptr::addr_of_mut!(BOOTSTRAP_HEAP_CHUNK_END);
 
     // Double check alignment and size of the bootstrap chunk
-    assert!(boot_chunk_start as usize % CHUNK_ALIGN == 0);
-    assert!(boot_chunk_end as usize - boot_chunk_start as usize == CHUNK_SIZE);
+    kassert_eq!(boot_chunk_start as usize % CHUNK_ALIGN, 0, "bootstrap heap chunk is misaligned");
+    kassert_eq!(
+        boot_chunk_end as usize - boot_chunk_start as usize,
+        CHUNK_SIZE,
+        "bootstrap heap chunk has the wrong size, check the linker script"
+    );
 
     let total_heap_chunks = (usize::MAX - boot_chunk_start as usize) / CHUNK_SIZE;
     let max_heap_size_mib = total_heap_chunks * 2;
@@ -103,7 +181,7 @@ pub fn init() {
     // Initialize boot chunk header
     #[allow(clippy::cast_ptr_alignment, reason = "Pointer ensured to be aligned")]
     let boot_chunk_hdr = boot_chunk_start.cast::<ChunkHeader>();
-    assert!(boot_chunk_hdr.is_aligned());
+    kassert!(boot_chunk_hdr.is_aligned(), "bootstrap chunk header at {boot_chunk_hdr:p} is misaligned");
 
     let free_chunks_after_boot_chunk = total_heap_chunks - 1;
 
@@ -115,7 +193,7 @@ pub fn init() {
                 num_unmapped_chunks: free_chunks_after_boot_chunk,
                 next: None,
             },
-            slot_metadatas: [(0, 0); 510],
+            slot_metadatas: [SlotMetadata::EMPTY; 510],
         });
     }
 
@@ -153,15 +231,14 @@ pub fn init() {
     let first_slot = boot_chunk_hdr.slot_ptr(0).cast::<FreeSlotHeader>();
     let unmapped_area_node = NonNull::new(unmapped_area_node).expect("`unmapped_area_node` pointer is null");
 
-    *HEAP_ALLOC.lock() = Some(HeapAlloc {
+    Spinlock::new(HeapAlloc {
         free_slot_list: Some(first_slot),
         unmapped_area_list: Some(unmapped_area_node),
-    });
+    })
 }
 
 pub fn alloc_slot() -> NonNull<u8> {
-    let mut guard = HEAP_ALLOC.lock();
-    let heap_alloc = guard.as_mut().expect("heap::init() not called yet");
+    let mut heap_alloc = HEAP_ALLOC.get().expect("heap::init() not called yet").lock();
 
     // Get a free slot from the head of the free slot list
     let Some(free_slot_ptr) = heap_alloc.free_slot_list else {
@@ -191,18 +268,37 @@ pub fn alloc_slot() -> NonNull<u8> {
 }
 
 pub fn free_slot(slot_ptr: NonNull<u8>) {
-    let mut guard = HEAP_ALLOC.lock();
-    let heap_alloc = guard.as_mut().expect("heap::init() not called yet");
+    let mut heap_alloc = HEAP_ALLOC.get().expect("heap::init() not called yet").lock();
+
+    kassert_eq!(slot_ptr.addr().get() % SLOT_ALIGN, 0, "freed slot {slot_ptr:p} is misaligned");
+
+    let (chunk_hdr, _) = slot_info(slot_ptr);
+    let boot_chunk_addr = core::ptr::addr_of!(BOOTSTRAP_HEAP_CHUNK_START) as usize;
+    let is_boot_chunk = chunk_hdr.addr().get() == boot_chunk_addr;
 
-    assert!(slot_ptr.addr().get() % SLOT_ALIGN == 0);
+    // The heap grows upwards starting at the bootstrap chunk, so anything below it isn't a heap slot
+    kassert!(
+        chunk_hdr.addr().get() >= boot_chunk_addr,
+        "freed slot {slot_ptr:p} is not part of the kernel heap"
+    );
+
+    // A slot that's already on the free list would otherwise get linked in twice, corrupting the list
+    // This walks the whole free list, so it's only checked in debug builds
+    debug_kassert!(!heap_alloc.is_free(slot_ptr), "double free of slot {slot_ptr:p}");
 
     // Decrement num allocs in this chunk
+    // Safety: `slot_ptr` is an allocated slot and we're holding the `HEAP_ALLOC` lock
     unsafe {
         update_chunk_num_alloc_slots(slot_ptr, |num_allocs| {
+            kassert!(
+                *num_allocs > 0,
+                "freed slot {slot_ptr:p} but its chunk has no allocated slots, double free?"
+            );
             *num_allocs -= 1;
 
-            if *num_allocs == 0 {
-                todo!("num allocs in this chunk reached 0, free this");
+            // The bootstrap chunk is part of the kernel's BSS, so it is never reclaimed even when empty
+            if *num_allocs == 0 && !is_boot_chunk {
+                todo!("num allocs in this chunk reached 0, unmap it and return it to the page allocator");
             }
         });
     }
@@ -222,23 +318,52 @@ pub fn free_slot(slot_ptr: NonNull<u8>) {
     heap_alloc.free_slot_list = Some(slot_ptr);
 }
 
-pub unsafe fn update_slot_metadata(ptr: NonNull<u8>, f: impl Fn(&mut (u64, u64))) {
+/// Runs `f` on the metadata of the slot that `ptr` points into
+///
+/// # Safety
+/// - `ptr` must point into a slot that is currently allocated (obtained from [`alloc_slot()`])
+/// - The caller must be the slot's owner and have exclusive access to it (see [`ChunkHeader`])
+///
+/// # Memory ordering
+/// The metadata is accessed without holding the `HEAP_ALLOC` lock and without any atomics or
+/// fences. This is sound because a slot (and its metadata) can only change owners by going
+/// through [`free_slot()`] and then [`alloc_slot()`], both of which take the `HEAP_ALLOC` lock.
+/// The lock's release (in `free_slot()`) and acquire (in `alloc_slot()`) orderings make every
+/// metadata write by the previous owner visible to the next owner, even if it runs on another core
+pub unsafe fn update_slot_metadata(ptr: NonNull<u8>, f: impl Fn(&mut SlotMetadata)) {
     let (chunk_hdr, slot_idx) = slot_info(ptr);
     let array_offset = core::mem::offset_of!(ChunkHeader, slot_metadatas);
 
-    let array_ptr = chunk_hdr.byte_add(array_offset).cast::<(u64, u64)>();
+    let array_ptr = chunk_hdr.byte_add(array_offset).cast::<SlotMetadata>();
     let mut metadata_ptr = array_ptr.add(slot_idx);
 
     let metadata = metadata_ptr.as_mut();
     f(metadata);
 }
 
+/// Returns a copy of the metadata of the slot that `ptr` points into
+///
+/// # Safety
+/// Same as [`update_slot_metadata()`]
+pub unsafe fn slot_metadata(ptr: NonNull<u8>) -> SlotMetadata {
+    let (chunk_hdr, slot_idx) = slot_info(ptr);
+    let array_offset = core::mem::offset_of!(ChunkHeader, slot_metadatas);
+
+    let array_ptr = chunk_hdr.byte_add(array_offset).cast::<SlotMetadata>();
+    array_ptr.add(slot_idx).read()
+}
+
+/// Runs `f` on the `num_alloc_slots` counter of the chunk that `ptr` points into
+///
+/// # Safety
+/// - `ptr` must point into a slot of a valid heap chunk
+/// - The caller must be holding the `HEAP_ALLOC` lock, which owns `num_alloc_slots`
 unsafe fn update_chunk_num_alloc_slots(ptr: NonNull<u8>, f: impl Fn(&mut usize)) {
     let (chunk_hdr, _) = slot_info(ptr);
     let offset = core::mem::offset_of!(ChunkHeader, num_alloc_slots);
 
     let mut num_allocs_ptr = chunk_hdr.byte_add(offset).cast::<usize>();
-    assert!(num_allocs_ptr.is_aligned());
+    kassert!(num_allocs_ptr.is_aligned(), "chunk header at {chunk_hdr:p} is misaligned");
 
     let num_allocs = num_allocs_ptr.as_mut();
     f(num_allocs);
@@ -256,8 +381,12 @@ fn slot_info(ptr: NonNull<u8>) -> (NonNull<ChunkHeader>, usize) {
     // Use the difference between chunk header address and slot address to calc absolute slot idx
     let abs_slot_idx = (slot_addr - chunk_hdr_addr) / SLOT_SIZE;
 
-    let slot_idx = abs_slot_idx - 2;
-    assert!(slot_idx < SLOTS_PER_CHUNK);
+    // First 2 slots are occupied by the header, so shift the index down by 2
+    let slot_idx = abs_slot_idx.checked_sub(2);
+    kassert!(slot_idx.is_some(), "{ptr:p} points into a chunk header, not a heap slot");
+    let slot_idx = slot_idx.expect("checked above");
+
+    kassert!(slot_idx < SLOTS_PER_CHUNK, "{ptr:p} does not point into a heap slot");
 
     let chunk_hdr_ptr = NonNull::new(chunk_hdr_addr as *mut ChunkHeader).expect("`chunk_hdr_addr` is null");
 
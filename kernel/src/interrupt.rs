@@ -0,0 +1,145 @@
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+};
+
+use crate::{
+    boot::{self, Phase},
+    debug_print::{HEADING, SUBHEADING},
+    debug_println, gdt, serial,
+    sync::Once,
+};
+
+static IDT: Once<InterruptDescriptorTable> = Once::new();
+
+/// Sets up the interrupt descriptor table and loads it
+pub fn init() {
+    debug_println!(HEADING; "Setting up interrupt handlers");
+
+    let idt = IDT.call_once(|| {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+
+        // Safety: The IST index points to the double fault stack, which `gdt::init()` set up in the TSS
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+
+        idt.general_protection_fault
+            .set_handler_fn(general_protection_fault_handler);
+        idt.page_fault.set_handler_fn(page_fault_handler);
+
+        idt
+    });
+
+    idt.load();
+
+    boot::complete(Phase::Interrupt);
+}
+
+/// Prints the interrupted state and returns, so execution continues after the `int3`
+extern "x86-interrupt" fn breakpoint_handler(frame: InterruptStackFrame) {
+    print_exception("Breakpoint hit", &frame, None);
+}
+
+extern "x86-interrupt" fn divide_error_handler(frame: InterruptStackFrame) {
+    fatal_exception();
+    print_exception("Divide error", &frame, None);
+    panic!("Unhandled divide error");
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(frame: InterruptStackFrame) {
+    fatal_exception();
+    print_exception("Invalid opcode", &frame, None);
+    panic!("Unhandled invalid opcode");
+}
+
+/// The error code is the selector index of the segment that caused the fault, or 0 if it wasn't
+/// segment related
+extern "x86-interrupt" fn general_protection_fault_handler(frame: InterruptStackFrame, error_code: u64) {
+    fatal_exception();
+    print_exception("General protection fault", &frame, Some(error_code));
+    panic!("Unhandled general protection fault");
+}
+
+/// Double faults can't be returned from, and the error code is always 0
+extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame, error_code: u64) -> ! {
+    fatal_exception();
+    print_exception("Double fault", &frame, Some(error_code));
+    panic!("Double fault");
+}
+
+/// Prints what caused the fault, then panics since we can't recover from page faults yet
+extern "x86-interrupt" fn page_fault_handler(frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
+    fatal_exception();
+
+    // CR2 holds the address whose access caused the fault
+    let addr = Cr2::read().as_u64();
+
+    let cause = if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        "protection violation"
+    } else {
+        "page not present"
+    };
+
+    let access = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        "instruction fetch"
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        "write"
+    } else {
+        "read"
+    };
+
+    let mode = if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        "user"
+    } else {
+        "kernel"
+    };
+
+    debug_println!(HEADING; "Page fault at 0x{addr:016X}");
+    debug_println!(SUBHEADING; "Cause: {cause} on {mode} mode {access}");
+    debug_println!(SUBHEADING; "Error code: 0x{:X} {error_code:?}", error_code.bits());
+    print_frame(&frame);
+
+    panic!("Unhandled page fault at 0x{addr:016X}");
+}
+
+/// Prepares for reporting an exception that ends in a panic
+///
+/// The exception may have been raised while the serial or debug printer lock was held, so this
+/// switches to the panic handler's lock-free output first. Otherwise printing the exception would
+/// spin on the lock forever and the panic would never be reported
+fn fatal_exception() {
+    serial::enter_panic_mode();
+}
+
+/// Prints the exception's `name` and the interrupted state, along with its error code if it has one
+fn print_exception(name: &str, frame: &InterruptStackFrame, error_code: Option<u64>) {
+    debug_println!(HEADING; "{name}");
+
+    if let Some(error_code) = error_code {
+        debug_println!(SUBHEADING; "Error code: 0x{error_code:X}");
+    }
+
+    print_frame(frame);
+}
+
+fn print_frame(frame: &InterruptStackFrame) {
+    print_frame_fields(
+        frame.instruction_pointer.as_u64(),
+        frame.code_segment,
+        frame.stack_pointer.as_u64(),
+        frame.cpu_flags,
+    );
+}
+
+/// Prints the fields of an interrupt stack frame
+#[allow(clippy::similar_names, reason = "Parameters are named after the registers")]
+fn print_frame_fields(rip: u64, cs: u64, rsp: u64, rflags: u64) {
+    debug_println!(SUBHEADING; "RIP: 0x{rip:016X}  CS: 0x{cs:04X}");
+    debug_println!(SUBHEADING; "RSP: 0x{rsp:016X}  RFLAGS: 0x{rflags:016X}");
+}
@@ -1,5 +1,6 @@
 #![no_std]
 #![no_main]
+#![feature(abi_x86_interrupt)]
 // Enable all lint groups except restriction
 #![deny(
     clippy::all,
@@ -39,24 +40,33 @@
 #![allow(clippy::module_name_repetitions, reason = "Module name repetition is fine actually")]
 
 mod arena;
+mod boot;
 mod cpuid;
+mod debug;
 mod debug_print;
+mod gdt;
 mod heap;
+mod interrupt;
 mod map;
 mod page_alloc;
+mod serial;
+mod symbols;
+mod sync;
 
 use core::fmt::Write;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU8, Ordering};
 
 use limine::{
-    request::{FramebufferRequest, HhdmRequest, MemoryMapRequest},
+    request::{FramebufferRequest, HhdmRequest, KernelFileRequest, MemoryMapRequest},
     BaseRevision,
 };
 
 use map::Map;
 use x86_64::instructions::{hlt, interrupts::disable as disable_interrupts};
 
-use debug_print::HEADING;
+use debug::RegisterDump;
+use debug_print::{HEADING, SUBHEADING};
 
 // Limine bootloader requests
 //
@@ -71,10 +81,15 @@ pub static HHDM_REQUEST: HhdmRequest = HhdmRequest::new();
 pub static FRAMEBUFFER_REQUEST: FramebufferRequest = FramebufferRequest::new();
 #[used]
 pub static MEM_MAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
+#[used]
+pub static KERNEL_FILE_REQUEST: KernelFileRequest = KernelFileRequest::new();
 
 /// Kernel entry point
 #[no_mangle]
 extern "C" fn _start() -> ! {
+    // Mark the top of the stack for backtraces
+    debug::record_stack_top();
+
     // Disable interrupts (just to be sure)
     disable_interrupts();
 
@@ -82,11 +97,18 @@ extern "C" fn _start() -> ! {
     assert!(BASE_REVISION.is_supported());
 
     // Start setting everything up
+    serial::init();
     debug_print::init();
+
+    // Wipe whatever the bootloader left on screen
+    debug_print::clear();
     debug_println!(HEADING; "Kernel started");
 
     cpuid::check();
     heap::init();
+    gdt::init();
+    interrupt::init();
+    symbols::init();
 
     let mut map: Map<u64> = map::Map::new();
     let n = 26;
@@ -99,13 +121,63 @@ extern "C" fn _start() -> ! {
         debug_println!("{:?}", map.get(i));
     }
 
+    #[cfg(feature = "test_panic")]
+    {
+        debug::breakpoint();
+        debug::test_panic();
+    }
+
     loop {
         hlt();
     }
 }
 
+/// Number of panics raised so far, used to detect panics raised by the panic handler itself
+///
+/// There's no per-CPU data yet, but only the bootstrap processor runs kernel code for now
+static PANIC_DEPTH: AtomicU8 = AtomicU8::new(0);
+
 #[panic_handler]
 fn rust_panic(info: &PanicInfo) -> ! {
+    // Capture registers first, before printing clobbers them
+    let regs = RegisterDump::capture();
+
+    // The code that panicked may be holding the serial or debug printer lock, and serial is often
+    // the only output there is (e.g. headless CI runs). Switch serial to lock-free writes before
+    // printing anything, this also makes the debug printer get skipped if it's locked
+    serial::enter_panic_mode();
+
+    match PANIC_DEPTH.fetch_add(1, Ordering::Relaxed) {
+        // First panic, print everything below
+        //
+        // None of it waits on a lock the panicking code might be holding: serial is in panic mode,
+        // the debug printer is skipped if it's locked, and the registers, backtrace and symbol
+        // table are read without locking
+        0 => {}
+
+        // If we panicked while handling a panic, the debug printer lock may be held or the panic
+        // may have come from printing itself. Print a minimal message without taking any locks and
+        // halt right away
+        1 => {
+            match info.location() {
+                Some(location) => debug_print::emergency_print(format_args!("**** NESTED KERNEL PANIC at {location} ****")),
+                None => debug_print::emergency_print(format_args!("**** NESTED KERNEL PANIC ****")),
+            }
+
+            halt();
+        }
+
+        // Even the minimal message panicked, just halt
+        _ => halt(),
+    }
+
+    // A panic before `debug_print::init()` has run (during early boot) would otherwise print
+    // nothing, so set up the debug printer here. If the panic happened while the printer was being
+    // initialized this panics again, and the nested panic path above takes over
+    if !debug_print::is_initialized() {
+        debug_print::init();
+    }
+
     debug_println!("\n**** KERNEL PANIC ****\n");
 
     debug_print!("Kernel panic occured at: ");
@@ -117,6 +189,25 @@ fn rust_panic(info: &PanicInfo) -> ! {
 
     _ = write!(debug_print::Helper, "\nMessage: {}", info.message());
 
+    debug_println!("\n\nRegisters:");
+    regs.print();
+
+    debug_println!("\nBacktrace:");
+
+    for (i, return_addr) in debug::backtrace().enumerate() {
+        // Resolve the address of the call instruction rather than the return address, which is
+        // past the end of the caller if the call was its last instruction
+        match symbols::resolve(return_addr.wrapping_sub(1)) {
+            Some((name, offset)) => debug_println!(SUBHEADING; "#{i}: 0x{return_addr:016X} {name}+0x{:X}", offset + 1),
+            None => debug_println!(SUBHEADING; "#{i}: 0x{return_addr:016X}"),
+        }
+    }
+
+    halt();
+}
+
+/// Halts the CPU forever
+fn halt() -> ! {
     disable_interrupts();
 
     loop {
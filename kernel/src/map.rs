@@ -1,4 +1,5 @@
 use core::cmp::Ordering;
+use core::marker::PhantomData;
 use core::ptr::NonNull;
 
 use arrayvec::ArrayVec;
@@ -7,6 +8,12 @@ use crate::arena::Arena;
 
 const ORDER: usize = 8;
 
+/// Upper bound on the tree's height, used to size the stacks of tree traversals
+///
+/// Every node apart from the root has at least `ORDER / 2 - 1` keys, so a tree this tall would
+/// need more keys than there are `u64` values
+const MAX_HEIGHT: usize = 32;
+
 type NodePtr<V> = NonNull<Node<V>>;
 type Children<V> = ArrayVec<NodePtr<V>, { ORDER + 1 }>;
 
@@ -85,6 +92,60 @@ impl<V> Map<V> {
         }
     }
 
+    /// Returns the entry with the largest key that is less than or equal to `key`
+    #[allow(dead_code, reason = "Nothing needs nearest key lookups yet")]
+    pub fn get_nearest(&self, key: u64) -> Option<(u64, &V)> {
+        // Iterate down the tree, keeping track of the closest lower key seen so far
+        let mut node = &self.root;
+        let mut nearest = None;
+
+        loop {
+            // Safety: Every node reachable from the root is a valid allocation in `node_arena`
+            let n = unsafe { node.as_ref() };
+
+            match n.keys.binary_search(&key) {
+                // Exact key found
+                Ok(idx) => return Some((key, n.values.get(idx).expect("Value not found"))),
+
+                // Key not found, `idx` is where it would be inserted
+                Err(idx) => {
+                    // The key just before the insertion point is lower than `key`, and closer than
+                    // any lower key in the levels above
+                    if let Some(prev_idx) = idx.checked_sub(1) {
+                        let prev_key = n.keys.get(prev_idx).expect("Key not found");
+                        let prev_value = n.values.get(prev_idx).expect("Value not found");
+
+                        nearest = Some((*prev_key, prev_value));
+                    }
+
+                    match n.children {
+                        // This is an internal node, closer keys can only be in the child at the insertion point
+                        Some(children) => {
+                            // Safety: `children` is a valid allocation in `children_arena`
+                            let children = unsafe { children.as_ref() };
+                            node = children.get(idx).expect("Child node not found");
+                        }
+
+                        // This is a leaf node, there are no closer keys
+                        None => return nearest,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns an iterator over the entries in ascending key order
+    #[allow(dead_code, reason = "Nothing iterates over a map yet")]
+    pub fn iter(&self) -> Iter<'_, V> {
+        let mut iter = Iter {
+            stack: ArrayVec::new(),
+            _map: PhantomData,
+        };
+
+        iter.push_leftmost(self.root);
+        iter
+    }
+
     pub fn get_mut(&mut self, key: u64) -> Option<&mut V> {
         // Iterate down the tree till we find the key
         let mut node = &mut self.root;
@@ -136,6 +197,49 @@ impl<V> Map<V> {
         }
     }
 
+    /// Removes every entry, dropping the values
+    ///
+    /// The nodes are freed back to the arenas, which are kept around so that refilling the map
+    /// doesn't need new slots
+    #[allow(dead_code, reason = "Nothing reuses a map yet")]
+    pub fn clear(&mut self) {
+        self.free_all_nodes();
+
+        self.root = self.node_arena.alloc(Node {
+            keys: ArrayVec::new(),
+            values: ArrayVec::new(),
+            children: None,
+        });
+    }
+
+    /// Frees every node back to the arenas, dropping all the values
+    ///
+    /// This leaves `root` dangling, so it must be replaced or never used again
+    fn free_all_nodes(&mut self) {
+        // Walk the tree using an explicit stack instead of recursing
+        let mut stack: ArrayVec<NodePtr<V>, { MAX_HEIGHT * (ORDER + 1) }> = ArrayVec::new();
+        stack.push(self.root);
+
+        while let Some(node) = stack.pop() {
+            // Safety: Every node reachable from the root is allocated from `node_arena` and is visited exactly once
+            let children = unsafe { node.as_ref() }.children;
+
+            if let Some(children) = children {
+                // Safety: `children` is allocated from `children_arena` and only referenced by this node
+                stack.extend(unsafe { children.as_ref() }.iter().copied());
+                self.children_arena.free(children);
+            }
+
+            // `Arena` never drops what it holds, so drop the node's keys and values before freeing it
+            // Safety: The node is valid and nothing else references it anymore
+            unsafe {
+                core::ptr::drop_in_place(node.as_ptr());
+            }
+
+            self.node_arena.free(node);
+        }
+    }
+
     /// Recursive B tree insert operation
     ///
     /// This function tries to insert a key/value pair into a node, splitting it if necessary (see [`SplitInfo`])
@@ -288,3 +392,73 @@ impl<V> Map<V> {
         }
     }
 }
+
+/// In order iterator over a [`Map`]'s entries, see [`Map::iter()`]
+pub struct Iter<'a, V> {
+    /// Path from the root to the current node, along with the index of the next key to visit in each node
+    stack: ArrayVec<(NodePtr<V>, usize), MAX_HEIGHT>,
+    _map: PhantomData<&'a Map<V>>,
+}
+
+impl<V> Iter<'_, V> {
+    /// Pushes `node` and its leftmost descendants onto the stack
+    fn push_leftmost(&mut self, mut node: NodePtr<V>) {
+        loop {
+            self.stack.push((node, 0));
+
+            // Safety: Every node reachable from the root is a valid allocation in `node_arena`, and
+            // the map can't be modified while it's borrowed by the iterator
+            let children = unsafe { node.as_ref() }.children;
+
+            match children {
+                Some(children) => {
+                    // Safety: `children` is a valid allocation in `children_arena`
+                    let children = unsafe { children.as_ref() };
+                    node = *children.first().expect("Child node not found");
+                }
+
+                None => break,
+            }
+        }
+    }
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (u64, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, idx) = self.stack.last_mut()?;
+
+            // Safety: Every node reachable from the root is a valid allocation in `node_arena`, and
+            // the map can't be modified while it's borrowed by the iterator
+            let n = unsafe { node.as_ref() };
+
+            let (Some(key), Some(value)) = (n.keys.get(*idx), n.values.get(*idx)) else {
+                // All keys in this node have been visited, go back up to the parent
+                self.stack.pop();
+                continue;
+            };
+
+            // Everything in the child to the right of this key comes before the next key
+            *idx += 1;
+
+            if let Some(children) = n.children {
+                // Safety: `children` is a valid allocation in `children_arena`
+                let children = unsafe { children.as_ref() };
+                let child = *children.get(*idx).expect("Child node not found");
+
+                self.push_leftmost(child);
+            }
+
+            return Some((*key, value));
+        }
+    }
+}
+
+impl<V> Drop for Map<V> {
+    /// Frees every node back to the arenas, dropping all the values
+    fn drop(&mut self) {
+        self.free_all_nodes();
+    }
+}
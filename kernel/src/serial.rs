@@ -0,0 +1,181 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spinning_top::Spinlock;
+use x86_64::instructions::port::Port;
+
+use crate::sync::Once;
+
+/// I/O port base of the COM1 serial port
+const COM1_BASE: u16 = 0x3F8;
+
+// 8250 UART register offsets from the base port
+const DATA: u16 = 0; // Transmit/receive buffer, or divisor latch low byte when DLAB is set
+const INT_ENABLE: u16 = 1; // Interrupt enable, or divisor latch high byte when DLAB is set
+const FIFO_CTRL: u16 = 2;
+const LINE_CTRL: u16 = 3;
+const MODEM_CTRL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+/// Line status bit that is set when the transmit holding register can accept a byte
+const LINE_STATUS_THR_EMPTY: u8 = 1 << 5;
+
+/// Standard 8250 UART init sequence, as `(register offset, value)` pairs written in order
+///
+/// Configures 38400 baud, 8 data bits, no parity, 1 stop bit, with FIFOs enabled and
+/// interrupts disabled
+const INIT_SEQUENCE: [(u16, u8); 7] = [
+    (INT_ENABLE, 0x00), // Disable interrupts
+    (LINE_CTRL, 0x80),  // Set DLAB to access the baud rate divisor
+    (DATA, 0x03),       // Divisor low byte (115200 / 3 = 38400 baud)
+    (INT_ENABLE, 0x00), // Divisor high byte
+    (LINE_CTRL, 0x03),  // Clear DLAB, 8 data bits, no parity, 1 stop bit
+    (FIFO_CTRL, 0xC7),  // Enable and clear FIFOs, 14 byte threshold
+    (MODEM_CTRL, 0x0B), // Assert DTR, RTS and OUT2
+];
+
+struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    /// Initializes the UART at `base`
+    fn new(base: u16) -> Self {
+        for (offset, value) in INIT_SEQUENCE {
+            let mut port = Port::<u8>::new(base + offset);
+
+            // Safety: Writing the standard init sequence to the UART's registers has no side effects
+            // apart from configuring the UART
+            unsafe {
+                port.write(value);
+            }
+        }
+
+        Self { base }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        // Safety: We own the UART at `self.base` through `&mut self`
+        unsafe {
+            write_byte_raw(self.base, byte);
+        }
+    }
+}
+
+/// Returns whether the UART can accept another byte, given the value of its line status register
+fn transmit_ready(line_status: u8) -> bool {
+    line_status & LINE_STATUS_THR_EMPTY != 0
+}
+
+/// Busy-waits till the UART at `base` can accept another byte, then transmits `byte`
+///
+/// # Safety
+/// Nothing else may be writing to the UART at the same time, or bytes can get lost or interleaved
+unsafe fn write_byte_raw(base: u16, byte: u8) {
+    let mut line_status = Port::<u8>::new(base + LINE_STATUS);
+    let mut data = Port::<u8>::new(base + DATA);
+
+    // Safety: Reading the line status register has no side effects
+    while !transmit_ready(unsafe { line_status.read() }) {
+        core::hint::spin_loop();
+    }
+
+    // Safety: Writing to the data register just transmits the byte
+    unsafe {
+        data.write(byte);
+    }
+}
+
+impl core::fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            // Serial terminals expect CRLF line endings
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+
+            self.write_byte(byte);
+        }
+
+        Ok(())
+    }
+}
+
+static SERIAL: Once<Spinlock<SerialPort>> = Once::new();
+
+/// Set once the kernel panics, after which all output bypasses the `SERIAL` lock
+static PANIC_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Initializes the COM1 serial port for debug output
+///
+/// This has no dependencies, so it can be called first thing during boot
+pub fn init() {
+    SERIAL.call_once(|| Spinlock::new(SerialPort::new(COM1_BASE)));
+}
+
+/// Writes `s` to the serial port, does nothing if [`init()`] hasn't been called
+///
+/// After [`enter_panic_mode()`] this writes to the UART directly instead, even if [`init()`]
+/// hasn't been called
+pub fn write_str(s: &str) {
+    if in_panic_mode() {
+        // Safety: Only the panic handler runs once panic mode is entered, so nothing else writes
+        // to the UART
+        unsafe {
+            serial_emergency_write(s.as_bytes());
+        }
+    } else if let Some(serial) = SERIAL.get() {
+        _ = core::fmt::Write::write_str(&mut *serial.lock(), s);
+    } else {
+        // Not initialized yet, drop the output
+    }
+}
+
+/// Formats `args` and writes them with [`write_str()`]
+pub fn write_fmt(args: core::fmt::Arguments) {
+    struct Writer;
+
+    impl core::fmt::Write for Writer {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            write_str(s);
+            Ok(())
+        }
+    }
+
+    _ = core::fmt::write(&mut Writer, args);
+}
+
+/// Makes all further serial output skip the `SERIAL` lock
+///
+/// The panic handler calls this first thing, since the code that panicked may be holding the lock.
+/// There's no way back, the kernel only ever halts after a panic
+pub fn enter_panic_mode() {
+    PANIC_MODE.store(true, Ordering::Release);
+}
+
+/// Returns whether [`enter_panic_mode()`] has been called
+pub fn in_panic_mode() -> bool {
+    PANIC_MODE.load(Ordering::Acquire)
+}
+
+/// Writes `bytes` to COM1 without taking any locks, converting `\n` to CRLF
+///
+/// The UART is driven with its power-on settings if [`init()`] hasn't been called, which most
+/// emulators and serial consoles still accept. Only meant for the panic path
+///
+/// # Safety
+/// Nothing else may be writing to the UART at the same time, or bytes can get lost or interleaved
+pub unsafe fn serial_emergency_write(bytes: &[u8]) {
+    for &byte in bytes {
+        if byte == b'\n' {
+            // Safety: Guaranteed by the caller
+            unsafe {
+                write_byte_raw(COM1_BASE, b'\r');
+            }
+        }
+
+        // Safety: Guaranteed by the caller
+        unsafe {
+            write_byte_raw(COM1_BASE, byte);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use xmas_elf::{
+    sections::SectionData,
+    symbol_table::{Entry, Entry64, Type},
+    ElfFile,
+};
+
+use crate::{
+    boot::{self, Phase},
+    debug_print::{Level, HEADING, SUBHEADING},
+    debug_println,
+    sync::Once,
+    KERNEL_FILE_REQUEST,
+};
+
+/// The kernel's own ELF symbol table
+struct SymbolTable {
+    elf: ElfFile<'static>,
+    symbols: &'static [Entry64],
+}
+
+// Holds `None` if the symbol table isn't available, in which case addresses are left unresolved
+static SYMBOL_TABLE: Once<Option<SymbolTable>> = Once::new();
+
+/// Loads the kernel's symbol table, used to resolve backtrace addresses
+///
+/// The symbol table is read from the kernel ELF file the bootloader loaded us from. This is
+/// optional: if the bootloader doesn't provide the file or the kernel was built stripped, symbol
+/// resolution is just disabled. It can also be turned off entirely by disabling the
+/// `symbolize_backtraces` feature
+pub fn init() {
+    debug_println!(HEADING; "Loading kernel symbols");
+
+    // Leaving `SYMBOL_TABLE` uninitialized makes `resolve()` always return `None`
+    if !cfg!(feature = "symbolize_backtraces") {
+        debug_println!(SUBHEADING; "Backtrace symbolization disabled");
+        boot::complete(Phase::Symbols);
+        return;
+    }
+
+    let symbol_table = SYMBOL_TABLE.call_once(load_symbol_table);
+
+    match symbol_table {
+        Some(table) => debug_println!(SUBHEADING; "Loaded {} symbols", table.symbols.len()),
+        None => debug_println!(level: Level::Warn; "No symbol table available, backtraces won't be symbolized"),
+    }
+
+    boot::complete(Phase::Symbols);
+}
+
+fn load_symbol_table() -> Option<SymbolTable> {
+    let kernel_file = KERNEL_FILE_REQUEST.get_response()?.file();
+
+    #[allow(clippy::cast_possible_truncation, reason = "usize and u64 have same size here")]
+    let kernel_file_size = kernel_file.size() as usize;
+
+    // Safety: The bootloader gives us the address and size of the kernel file. It lives in
+    // bootloader reclaimable memory, but the kernel never reclaims that memory, and must not
+    // while the symbol table borrows from it for the rest of the kernel's lifetime
+    let kernel_file = unsafe { core::slice::from_raw_parts(kernel_file.addr().cast_const(), kernel_file_size) };
+
+    let elf = ElfFile::new(kernel_file).ok()?;
+    let symtab = elf.find_section_by_name(".symtab")?;
+
+    match symtab.get_data(&elf).ok()? {
+        SectionData::SymbolTable64(symbols) => Some(SymbolTable { elf, symbols }),
+        _ => None,
+    }
+}
+
+/// Finds the function containing `addr`
+///
+/// Returns the function's (mangled) name and the offset of `addr` from its start, or `None` if
+/// no function contains it or the symbol table isn't loaded
+pub fn resolve(addr: u64) -> Option<(&'static str, u64)> {
+    let table = SYMBOL_TABLE.get()?.as_ref()?;
+
+    let functions = table
+        .symbols
+        .iter()
+        .filter(|symbol| matches!(symbol.get_type(), Ok(Type::Func)))
+        .filter_map(|symbol| Some((symbol.get_name(&table.elf).ok()?, symbol.value(), symbol.size())));
+
+    find_symbol(functions, addr)
+}
+
+/// Searches `symbols` for the one whose address range contains `addr`
+///
+/// Symbols are given as `(name, start address, size)` and returned as `(name, offset from start)`
+fn find_symbol<'a>(mut symbols: impl Iterator<Item = (&'a str, u64, u64)>, addr: u64) -> Option<(&'a str, u64)> {
+    symbols
+        .find(|&(_, start, size)| addr >= start && addr - start < size)
+        .map(|(name, start, _)| (name, addr - start))
+}
@@ -0,0 +1,67 @@
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const UNINIT: u8 = 0;
+const INITIALIZING: u8 = 1;
+const INIT: u8 = 2;
+
+/// A value that is initialized exactly once and is then only accessed through shared references
+///
+/// This is meant for globals that are set up by a subsystem's `init()` function. Unlike the
+/// `Spinlock<Option<T>>` pattern, initializing twice is caught, and accessing the value after
+/// initialization is a single atomic load with no locking. If the value needs to be mutated
+/// after initialization, wrap it in a lock (`Once<Spinlock<T>>`)
+pub struct Once<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Safety: `value` is written exactly once, by whoever moves `state` from `UNINIT` to `INITIALIZING`,
+// and is only read after `INIT` has been observed with acquire ordering. After that it's only ever
+// accessed through shared references, so sharing a `Once<T>` is equivalent to sharing a `&T` that
+// may have been created on another thread
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+
+impl<T> Once<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(UNINIT),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Initializes the value with the result of `f` and returns a reference to it
+    ///
+    /// # Panics
+    /// Panics if the value has already been initialized, or is being initialized concurrently
+    pub fn call_once(&self, f: impl FnOnce() -> T) -> &T {
+        let claimed = self
+            .state
+            .compare_exchange(UNINIT, INITIALIZING, Ordering::Acquire, Ordering::Relaxed);
+
+        assert!(claimed.is_ok(), "Once value initialized twice");
+
+        // Safety: We moved `state` out of `UNINIT` so nobody else will write to `value`, and nobody will
+        // read it until we set `state` to `INIT` below
+        let value = unsafe { &mut *self.value.get() };
+        let value = value.write(f());
+
+        self.state.store(INIT, Ordering::Release);
+
+        value
+    }
+
+    /// Returns a reference to the value, or `None` if it hasn't been initialized yet
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) != INIT {
+            return None;
+        }
+
+        // Safety: `state` is `INIT`, so `value` has been written and will never be written again
+        let value = unsafe { &*self.value.get() };
+
+        // Safety: `value` was initialized by `call_once()`
+        Some(unsafe { value.assume_init_ref() })
+    }
+}